@@ -1,36 +1,69 @@
-use std::{env, sync::Arc, time::Duration};
+use std::{env, path::PathBuf, sync::Arc, time::Duration};
 
+use dashmap::DashMap;
 use moka::future::{Cache, CacheBuilder};
 use octocrab::Octocrab;
 use rayon::{ThreadPool, ThreadPoolBuilder};
 use tokio::{
 	sync::{
+		broadcast,
 		oneshot::{channel, Sender},
 		Mutex,
+		Semaphore,
 	},
 	time::sleep,
 };
 use tracing::{error, info};
+use wt_version::Version;
 
 use crate::{
+	content_index::ContentIndex,
 	endpoints::{
 		files::{FileRequest, UnpackedVromfs},
 		get_vromfs,
 		get_vromfs::VromfCache,
+		query,
 	},
 	error::ApiError,
+	events::{self, VersionEvent},
 	eyre_error_translation::EyreToApiError,
+	feed::FeedEntry,
+	lang_table::LangTable,
+	rate_limit::{Priority, RateLimit},
+	unit_catalog::{self, UnitCatalog},
+	weapon_catalog::WeaponCatalog,
+	webhooks::Webhooks,
 };
 
 pub struct AppState {
 	// Contains binary VROMFs requested from github
 	pub vromf_cache:     VromfCache,
-	pub octocrab:        Mutex<Octocrab>,
+	pub octocrab:        Octocrab,
+	// Remaining GitHub quota, and whether requests are held back until it resets
+	pub rate_limit:      RateLimit,
 	// Initialized unpackers per VROMF
 	pub unpacked_vromfs: UnpackedVromfs,
 	worker_pool:         Arc<ThreadPool>,
 	// 	Request with content type and data
 	pub files_cache:     Cache<FileRequest, (Vec<u8>, &'static str)>,
+	// Full-text indices per version, None while the index is being built
+	pub content_indices: DashMap<Version, Option<Arc<ContentIndex>>>,
+	// Parsed lang.vromfs.bin CSVs per version
+	pub lang_tables:     DashMap<Version, Arc<LangTable>>,
+	// Units parsed from char.vromfs.bin per version, bounded as history requests parse many versions
+	pub unit_catalogs:   Cache<Version, Arc<UnitCatalog>>,
+	// Weapons parsed from aces.vromfs.bin per version
+	pub weapon_catalogs: DashMap<Version, Arc<WeaponCatalog>>,
+	// Held while an export is written to disk, so that it is only generated once
+	pub export_locks:    DashMap<PathBuf, Arc<Mutex<()>>>,
+	// Newly discovered versions, for SSE and WebSocket subscribers
+	pub version_events:  broadcast::Sender<Arc<VersionEvent>>,
+	// Subscriptions notified of new versions and their delivery log
+	pub webhooks:        Webhooks,
+	// Commit details of versions listed in the Atom feed
+	pub feed_entries:    DashMap<Version, Arc<FeedEntry>>,
+	// Permits for query worker processes, so that their memory limits add up to a known bound
+	pub query_workers:   Semaphore,
 }
 
 impl Default for AppState {
@@ -48,12 +81,22 @@ impl Default for AppState {
 
 		Self {
 			vromf_cache: Default::default(),
-			octocrab: Mutex::new(octocrab.build().unwrap()),
+			octocrab: octocrab.build().unwrap(),
+			rate_limit: Default::default(),
 			unpacked_vromfs: Default::default(),
 			worker_pool,
 			files_cache: CacheBuilder::new(100)
 				.time_to_live(Duration::from_secs(60)) // 😡😡😡😡😡 https://github.com/rust-lang/rust/issues/120301
 				.build(),
+			content_indices: Default::default(),
+			lang_tables: Default::default(),
+			unit_catalogs: CacheBuilder::new(unit_catalog::MAX_CACHED_CATALOGS).build(),
+			weapon_catalogs: Default::default(),
+			export_locks: Default::default(),
+			version_events: events::channel(),
+			webhooks: Webhooks::from_env(),
+			feed_entries: Default::default(),
+			query_workers: Semaphore::new(query::MAX_WORKERS),
 		}
 	}
 }
@@ -67,6 +110,14 @@ impl AppState {
 		self.worker_pool.spawn(|| f(s));
 		r.await.convert_err()
 	}
+
+	/// Runs parallel work on the worker pool from outside of it, blocking until it is done
+	pub fn install<F, R>(&self, f: F) -> R
+	where
+		F: FnOnce() -> R + Send,
+		R: Send, {
+		self.worker_pool.install(f)
+	}
 }
 
 pub fn cache_refresh_task(state: Arc<AppState>, sender: Sender<()>) {
@@ -74,7 +125,7 @@ pub fn cache_refresh_task(state: Arc<AppState>, sender: Sender<()>) {
 		let mut s = Some(sender);
 		loop {
 			{
-				let e = get_vromfs::pull_vromf_to_cache(state.clone(), None)
+				let e = get_vromfs::pull_vromf_to_cache(state.clone(), None, Priority::Background)
 					.await
 					.err();
 				if let Some(e) = e {
@@ -0,0 +1,136 @@
+use std::{io, io::Write, mem};
+
+use axum::body::Bytes;
+use flate2::{write::GzEncoder, Compression};
+use tar::{Builder, Header};
+use tokio::sync::mpsc;
+use zip::{
+	write::{SimpleFileOptions, StreamWriter},
+	CompressionMethod,
+	ZipWriter,
+};
+
+/// Archives are sent to clients in chunks of this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Default, Eq, PartialEq, Hash, Clone, Copy, strum::Display, strum::EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum ArchiveFormat {
+	#[default]
+	#[strum(serialize = "zip")]
+	Zip,
+	#[strum(serialize = "tar")]
+	Tar,
+	#[strum(serialize = "tar.gz")]
+	TarGz,
+	#[strum(serialize = "tar.zst")]
+	TarZst,
+}
+
+impl ArchiveFormat {
+	pub fn content_type(self) -> &'static str {
+		match self {
+			ArchiveFormat::Zip => "application/zip",
+			ArchiveFormat::Tar => "application/x-tar",
+			ArchiveFormat::TarGz => "application/gzip",
+			ArchiveFormat::TarZst => "application/zstd",
+		}
+	}
+}
+
+/// Incrementally written archive, which never needs to seek back into already written data
+pub enum ArchiveWriter<W: Write> {
+	Zip(ZipWriter<StreamWriter<W>>),
+	Tar(Builder<W>),
+	TarGz(Builder<GzEncoder<W>>),
+	TarZst(Builder<zstd::Encoder<'static, W>>),
+}
+
+impl<W: Write> ArchiveWriter<W> {
+	pub fn new(w: W, format: ArchiveFormat) -> io::Result<Self> {
+		Ok(match format {
+			ArchiveFormat::Zip => Self::Zip(ZipWriter::new_stream(w)),
+			ArchiveFormat::Tar => Self::Tar(Builder::new(w)),
+			ArchiveFormat::TarGz => {
+				Self::TarGz(Builder::new(GzEncoder::new(w, Compression::default())))
+			},
+			ArchiveFormat::TarZst => Self::TarZst(Builder::new(zstd::Encoder::new(w, 0)?)),
+		})
+	}
+
+	pub fn append(&mut self, path: &str, buf: &[u8]) -> io::Result<()> {
+		match self {
+			Self::Zip(z) => {
+				let options =
+					SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+				z.start_file(path, options)?;
+				z.write_all(buf)
+			},
+			Self::Tar(b) => b.append_data(&mut tar_header(buf), path, buf),
+			Self::TarGz(b) => b.append_data(&mut tar_header(buf), path, buf),
+			Self::TarZst(b) => b.append_data(&mut tar_header(buf), path, buf),
+		}
+	}
+
+	/// Writes the archive trailer and returns the underlying writer
+	pub fn finish(self) -> io::Result<W> {
+		match self {
+			Self::Zip(z) => Ok(z.finish()?.into_inner()),
+			Self::Tar(b) => b.into_inner(),
+			Self::TarGz(b) => b.into_inner()?.finish(),
+			Self::TarZst(b) => b.into_inner()?.finish(),
+		}
+	}
+}
+
+fn tar_header(buf: &[u8]) -> Header {
+	let mut header = Header::new_gnu();
+	header.set_size(buf.len() as u64);
+	header.set_mode(0o644);
+	header
+}
+
+/// Blocking writer forwarding chunks to an async receiver, meant for a blocking thread rather than the worker pool,
+/// as it waits on slow clients
+pub struct ChannelWriter {
+	sender: mpsc::Sender<io::Result<Bytes>>,
+	buf:    Vec<u8>,
+}
+
+impl ChannelWriter {
+	pub fn new(sender: mpsc::Sender<io::Result<Bytes>>) -> Self {
+		Self {
+			sender,
+			buf: Vec::with_capacity(CHUNK_SIZE),
+		}
+	}
+
+	/// Aborts the stream, so that the client notices the archive is incomplete
+	pub fn fail(self, e: io::Error) {
+		let _ = self.sender.blocking_send(Err(e));
+	}
+
+	fn send_buf(&mut self) -> io::Result<()> {
+		if self.buf.is_empty() {
+			return Ok(());
+		}
+		let chunk = mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+		self.sender
+			.blocking_send(Ok(chunk.into()))
+			.map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
+	}
+}
+
+impl Write for ChannelWriter {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.buf.extend_from_slice(buf);
+		if self.buf.len() >= CHUNK_SIZE {
+			self.send_buf()?;
+		}
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.send_buf()
+	}
+}
@@ -0,0 +1,162 @@
+use std::{
+	collections::{BTreeMap, HashMap},
+	fmt::Write,
+	fs,
+	path::Path,
+	sync::Arc,
+};
+
+use http::StatusCode;
+use octocrab::{models::repos::RepoCommit, Page};
+use tracing::{debug, error, info};
+use wt_version::Version;
+
+use crate::{
+	app_state::AppState,
+	endpoints::get_vromfs::{
+		parse_commit_version,
+		pull_vromf_to_cache,
+		read_commit_index,
+		DATAMINE_REPO,
+	},
+	error::ApiError,
+	export,
+	eyre_error_translation::EyreToApiError,
+	rate_limit::Priority,
+};
+
+const USAGE: &str = "Usage:
+	wt_dm_api                                      Runs the server
+	wt_dm_api export-sqlite <version|latest> [out] Writes a version's SQLite export to out
+	wt_dm_api regenerate-commits [out]             Rewrites the commit index from the upstream history";
+const DEFAULT_COMMITS_PATH: &str = "assets/commits.txt";
+
+/// Runs a subcommand instead of the server, returning the exit code
+pub async fn run(state: Arc<AppState>, args: &[String]) -> i32 {
+	let res = match args {
+		[cmd, version, rest @ ..] if cmd == "export-sqlite" && rest.len() <= 1 => {
+			export_sqlite(state, version, rest.first().map(String::as_str)).await
+		},
+		[cmd, rest @ ..] if cmd == "regenerate-commits" && rest.len() <= 1 => {
+			let out = rest.first().map_or(DEFAULT_COMMITS_PATH, String::as_str);
+			regenerate_commits(state, Path::new(out)).await
+		},
+		_ => {
+			eprintln!("{USAGE}");
+			return 2;
+		},
+	};
+	match res {
+		Ok(()) => 0,
+		Err((status, e)) => {
+			error!("{status}: {e}");
+			1
+		},
+	}
+}
+
+async fn export_sqlite(state: Arc<AppState>, version: &str, out: Option<&str>) -> ApiError<()> {
+	if version == "latest" {
+		// The embedded commit list only knows versions up to when it was generated
+		pull_vromf_to_cache(state.clone(), None, Priority::User).await?;
+	}
+	let version = state.vromf_cache.resolve_version(Some(version))?;
+	let path = export::sqlite(state, version).await?;
+	if let Some(out) = out {
+		fs::copy(&path, out).map_err(|e| {
+			(
+				StatusCode::INTERNAL_SERVER_ERROR,
+				format!("failed to copy {} to {out}: {e}", path.display()),
+			)
+		})?;
+		info!("Wrote {out}");
+	} else {
+		info!("Wrote {}", path.display());
+	}
+	Ok(())
+}
+
+async fn regenerate_commits(state: Arc<AppState>, out: &Path) -> ApiError<()> {
+	let commits = upstream_commits(&state).await?;
+
+	// History is listed newest first, of commits sharing a version the oldest one is kept
+	let mut index = BTreeMap::new();
+	for (version, sha) in commits.into_iter().rev() {
+		index.entry(version).or_insert(sha);
+	}
+	let previous = read_commit_index(out)
+		.into_iter()
+		.collect::<HashMap<_, _>>();
+
+	let mut contents = String::new();
+	for (version, sha) in index.iter().rev() {
+		writeln!(contents, "{sha} {version}").convert_err()?;
+	}
+	fs::write(out, contents).map_err(|e| {
+		(
+			StatusCode::INTERNAL_SERVER_ERROR,
+			format!("failed to write {}: {e}", out.display()),
+		)
+	})?;
+
+	let (mut added, mut changed) = (0, 0);
+	for (version, sha) in &index {
+		match previous.get(version) {
+			None => {
+				info!("Added {version} at {sha}");
+				added += 1;
+			},
+			Some(old) if old != sha => {
+				info!("Changed {version} from {old} to {sha}");
+				changed += 1;
+			},
+			Some(_) => {},
+		}
+	}
+	let mut removed = previous
+		.keys()
+		.filter(|version| !index.contains_key(version))
+		.collect::<Vec<_>>();
+	removed.sort_unstable();
+	for version in &removed {
+		info!("Removed {version}");
+	}
+	info!(
+		"Wrote {} versions to {}: {added} added, {changed} changed, {} removed",
+		index.len(),
+		out.display(),
+		removed.len()
+	);
+	Ok(())
+}
+
+/// Every commit of the datamine repository whose message names a version, newest first
+async fn upstream_commits(state: &AppState) -> ApiError<Vec<(Version, String)>> {
+	let octo = &state.octocrab;
+	let mut commits = vec![];
+	for page in 1_u32.. {
+		let res = state
+			.rate_limit
+			.call::<Page<RepoCommit>>(
+				octo,
+				Priority::User,
+				&format!("/repos/{DATAMINE_REPO}/commits?per_page=100&page={page}"),
+			)
+			.await?;
+		if res.items.is_empty() {
+			break;
+		}
+		for commit in res {
+			match parse_commit_version(&commit.commit.message) {
+				Some(version) => commits.push((version, commit.sha)),
+				None => debug!(
+					"Skipping commit {} without a version: {:?}",
+					commit.sha,
+					commit.commit.message.lines().next().unwrap_or_default()
+				),
+			}
+		}
+		info!("Crawled {page} pages, found {} versions", commits.len());
+	}
+	Ok(commits)
+}
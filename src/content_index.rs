@@ -0,0 +1,300 @@
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+use dashmap::mapref::entry::Entry;
+use serde::Serialize;
+use serde_json::Value;
+use strum::VariantArray;
+use tracing::{error, info};
+use utoipa::ToSchema;
+use wt_blk::vromf::{BlkOutputFormat, File};
+use wt_version::Version;
+
+use crate::{
+	app_state::AppState,
+	endpoints::files::UnpackedVromfs,
+	error::ApiError,
+	flatten::{leaf_to_string, visit_leaves},
+	vromf_enum::VromfType,
+};
+
+/// Non-BLK files that are indexed line by line
+const TEXT_EXTENSIONS: &[&str] = &["txt", "csv", "nut", "json", "xml", "cfg", "ini", "html"];
+/// Skips large text files which are mostly generated data
+const MAX_TEXT_FILE_SIZE: usize = 4 * 1024 * 1024;
+const SNIPPET_CONTEXT: usize = 40;
+/// Built indexes kept in memory, the oldest versions besides the latest are evicted first
+const MAX_CONTENT_INDICES: usize = 3;
+
+/// Inverted word index over keys and values of decoded BLKs and lines of plain text files
+#[derive(Default)]
+pub struct ContentIndex {
+	files:    Vec<(VromfType, String)>,
+	entries:  Vec<IndexEntry>,
+	// Lowercase word -> ascending ids of entries containing it
+	postings: BTreeMap<String, Vec<u32>>,
+}
+
+struct IndexEntry {
+	file:     u32,
+	location: Location,
+	text:     String,
+}
+
+enum Location {
+	Key(String),
+	Line(usize),
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ContentMatch {
+	vromf:    &'static str,
+	path:     String,
+	/// Key path of the matched value, set for BLK files
+	key_path: Option<String>,
+	/// 1-based line number of the match, set for plain text files
+	line:     Option<usize>,
+	snippet:  String,
+}
+
+impl ContentIndex {
+	/// Starts building the index for a version in the background, unless it is built or building already
+	pub fn schedule_build(state: Arc<AppState>, version: Version) {
+		match state.content_indices.entry(version) {
+			Entry::Occupied(_) => return,
+			Entry::Vacant(e) => {
+				e.insert(None);
+			},
+		}
+
+		tokio::spawn(async move {
+			info!("Building content index for {version}");
+			match Self::build(state.clone(), version).await {
+				Ok(index) => {
+					info!(
+						"Built content index for {version} with {} entries",
+						index.entries.len()
+					);
+					state.content_indices.insert(version, Some(Arc::new(index)));
+					Self::evict(&state, version);
+				},
+				Err(e) => {
+					error!(
+						"Failed to build content index for {version}. Reason: {}",
+						e.1
+					);
+					state.content_indices.remove(&version);
+				},
+			}
+		});
+	}
+
+	/// Drops indexes past MAX_CONTENT_INDICES, keeping the latest version and the one just built
+	fn evict(state: &AppState, built: Version) {
+		let latest = state.vromf_cache.latest_known_version();
+		let mut versions = state
+			.content_indices
+			.iter()
+			.filter(|e| e.value().is_some())
+			.map(|e| *e.key())
+			.collect::<Vec<_>>();
+		let excess = versions.len().saturating_sub(MAX_CONTENT_INDICES);
+		versions.retain(|v| *v != latest && *v != built);
+		versions.sort_unstable();
+		for version in versions.into_iter().take(excess) {
+			info!("Evicting content index for {version}");
+			state.content_indices.remove(&version);
+		}
+	}
+
+	async fn build(state: Arc<AppState>, version: Version) -> ApiError<Self> {
+		UnpackedVromfs::cache_unpacker(state.clone(), version, VromfType::Aces).await?;
+
+		state
+			.clone()
+			.spawn_worker(move |s| {
+				let res = || {
+					let mut index = Self::default();
+					for vromf in VromfType::VARIANTS {
+						let files = UnpackedVromfs::unpack_all(
+							&state,
+							version,
+							*vromf,
+							Some(BlkOutputFormat::Json),
+							true,
+						)?;
+						for file in files {
+							index.add_file(*vromf, file);
+						}
+					}
+					Ok(index)
+				};
+				let _ = s.send(res());
+			})
+			.await?
+	}
+
+	fn add_file(&mut self, vromf: VromfType, file: File) {
+		let (path, buf) = file.split();
+		let path = path.to_string_lossy().replace('\\', "/");
+		let file_id = self.files.len() as u32;
+
+		let extension = Path::new(&path)
+			.extension()
+			.and_then(|e| e.to_str())
+			.unwrap_or_default();
+		if extension == "blk" {
+			let Ok(value) = serde_json::from_slice::<Value>(&buf) else {
+				return;
+			};
+			visit_leaves(&value, &mut |key_path, leaf| {
+				if !leaf.is_null() {
+					self.add_entry(
+						file_id,
+						Location::Key(key_path.to_owned()),
+						leaf_to_string(leaf).into_owned(),
+					);
+				}
+			});
+		} else if TEXT_EXTENSIONS.contains(&extension) && buf.len() <= MAX_TEXT_FILE_SIZE {
+			let Ok(text) = String::from_utf8(buf) else {
+				return;
+			};
+			for (i, line) in text.lines().enumerate() {
+				if !line.trim().is_empty() {
+					self.add_entry(file_id, Location::Line(i + 1), line.to_owned());
+				}
+			}
+		} else {
+			return;
+		}
+		self.files.push((vromf, path));
+	}
+
+	/// Indexes the words of the text, and of the key path for BLK values
+	fn add_entry(&mut self, file: u32, location: Location, text: String) {
+		let id = self.entries.len() as u32;
+		let key_path = match &location {
+			Location::Key(k) => k.to_ascii_lowercase(),
+			Location::Line(_) => String::new(),
+		};
+		for word in words(&text.to_ascii_lowercase()).chain(words(&key_path)) {
+			let ids = self.postings.entry(word.to_owned()).or_default();
+			if ids.last() != Some(&id) {
+				ids.push(id);
+			}
+		}
+		self.entries.push(IndexEntry {
+			file,
+			location,
+			text,
+		});
+	}
+
+	/// Case-insensitive substring search over values and key paths, where every word of the query has to prefix a word of the match
+	pub fn search(&self, query: &str, limit: usize) -> Vec<ContentMatch> {
+		let query = query.to_ascii_lowercase();
+		let mut candidates: Option<Vec<u32>> = None;
+		for word in words(&query) {
+			let mut ids = self
+				.postings
+				.range(word.to_owned()..)
+				.take_while(|(k, _)| k.starts_with(word))
+				.flat_map(|(_, ids)| ids.iter().copied())
+				.collect::<Vec<_>>();
+			ids.sort_unstable();
+			ids.dedup();
+
+			candidates = Some(match candidates {
+				None => ids,
+				Some(prev) => prev
+					.into_iter()
+					.filter(|id| ids.binary_search(id).is_ok())
+					.collect(),
+			});
+		}
+
+		candidates
+			.unwrap_or_default()
+			.into_iter()
+			.filter_map(|id| {
+				let entry = &self.entries[id as usize];
+				let (key_path, line) = match &entry.location {
+					Location::Key(k) => (Some(k.clone()), None),
+					Location::Line(l) => (None, Some(*l)),
+				};
+				let snippet = match entry.text.to_ascii_lowercase().find(&query) {
+					Some(at) => snippet(&entry.text, at, query.len()),
+					// Matches on the key path show the start of the value
+					None if key_path
+						.as_ref()
+						.is_some_and(|k| k.to_ascii_lowercase().contains(&query)) =>
+					{
+						snippet(&entry.text, 0, 0)
+					},
+					None => return None,
+				};
+				let (vromf, path) = &self.files[entry.file as usize];
+				Some(ContentMatch {
+					vromf: vromf.into(),
+					path: path.clone(),
+					key_path,
+					line,
+					snippet,
+				})
+			})
+			.take(limit)
+			.collect()
+	}
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+	text.split(|c: char| !c.is_alphanumeric())
+		.filter(|w| !w.is_empty())
+}
+
+fn snippet(text: &str, at: usize, len: usize) -> String {
+	let mut start = at.saturating_sub(SNIPPET_CONTEXT);
+	while !text.is_char_boundary(start) {
+		start -= 1;
+	}
+	let mut end = (at + len + SNIPPET_CONTEXT).min(text.len());
+	while !text.is_char_boundary(end) {
+		end += 1;
+	}
+	text[start..end].trim().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn index() -> ContentIndex {
+		let mut index = ContentIndex::default();
+		index
+			.files
+			.push((VromfType::Aces, "gamedata/weapons/rocket.blk".to_owned()));
+		for (key_path, value) in [("rocket.maxDistance", "2500"), ("rocket.caliber", "0.07")] {
+			index.add_entry(0, Location::Key(key_path.to_owned()), value.to_owned());
+		}
+		index
+	}
+
+	#[test]
+	fn search_matches_scalar_values() {
+		let matches = index().search("0.07", 10);
+		assert_eq!(matches.len(), 1);
+		assert_eq!(matches[0].key_path.as_deref(), Some("rocket.caliber"));
+		assert_eq!(matches[0].snippet, "0.07");
+	}
+
+	#[test]
+	fn search_matches_key_paths() {
+		let matches = index().search("maxdist", 10);
+		assert_eq!(matches.len(), 1);
+		assert_eq!(matches[0].key_path.as_deref(), Some("rocket.maxDistance"));
+		assert_eq!(matches[0].snippet, "2500");
+
+		assert_eq!(index().search("rocket", 10).len(), 2);
+		assert!(index().search("range", 10).is_empty());
+	}
+}
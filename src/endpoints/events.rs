@@ -0,0 +1,82 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+	extract::{
+		ws::{Message, WebSocket, WebSocketUpgrade},
+		State,
+	},
+	response::{
+		sse::{Event, KeepAlive, Sse},
+		IntoResponse,
+	},
+};
+use futures::{stream, Stream};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::debug;
+
+use crate::{app_state::AppState, events::VersionEvent};
+
+#[utoipa::path(
+	get,
+	path = "/events",
+	responses(
+        (status = 200, description = "Server-sent events named \"version\", each holding a VersionEvent as JSON", body = VersionEvent, content_type = ["text/event-stream"]),
+	)
+)]
+pub async fn version_events(
+	State(state): State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+	let events = stream::unfold(state.version_events.subscribe(), |mut rx| async move {
+		let event = next_event(&mut rx).await?;
+		let json = serde_json::to_string(&*event).expect("event to serialize");
+		Some((Ok(Event::default().event("version").data(json)), rx))
+	});
+	Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[utoipa::path(
+	get,
+	path = "/events/ws",
+	responses(
+        (status = 101, description = "WebSocket sending one text message with a VersionEvent as JSON per new version", body = VersionEvent),
+	)
+)]
+pub async fn version_events_ws(
+	State(state): State<Arc<AppState>>,
+	ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+	let rx = state.version_events.subscribe();
+	ws.on_upgrade(move |socket| forward_events(socket, rx))
+}
+
+async fn forward_events(mut socket: WebSocket, mut rx: Receiver<Arc<VersionEvent>>) {
+	loop {
+		tokio::select! {
+			event = next_event(&mut rx) => {
+				let Some(event) = event else {
+					return;
+				};
+				let json = serde_json::to_string(&*event).expect("event to serialize");
+				if socket.send(Message::Text(json)).await.is_err() {
+					return;
+				}
+			},
+			// Clients only ever close the socket, anything else they send is ignored
+			msg = socket.recv() => match msg {
+				Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+				Some(Ok(_)) => {},
+			},
+		}
+	}
+}
+
+/// Next event of a subscription, skipping over events the subscriber lagged behind on
+async fn next_event(rx: &mut Receiver<Arc<VersionEvent>>) -> Option<Arc<VersionEvent>> {
+	loop {
+		match rx.recv().await {
+			Ok(event) => return Some(event),
+			Err(RecvError::Lagged(skipped)) => debug!("Subscriber skipped {skipped} events"),
+			Err(RecvError::Closed) => return None,
+		}
+	}
+}
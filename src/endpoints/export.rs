@@ -0,0 +1,78 @@
+use std::{path::Path as StdPath, sync::Arc};
+
+use axum::{
+	body::Body,
+	extract::{Path, State},
+	response::{IntoResponse, Response},
+};
+use http::StatusCode;
+use tokio_util::io::ReaderStream;
+
+use crate::{app_state::AppState, error::ApiError, export, eyre_error_translation::EyreToApiError};
+
+#[utoipa::path(
+	get,
+	path = "/export/{file}",
+	params(
+		("file" = String, description = "Version string or literal \"latest\", followed by the export format", example = "latest.sqlite"),
+	),
+	responses(
+        (status = 200, description = "SQLite database with the tables files, blk_values, lang and meta", content_type = ["application/vnd.sqlite3"]),
+		(status = 400, description = "Version invalid"),
+		(status = 404, description = "Unknown export format"),
+	)
+)]
+pub async fn export_version(
+	State(state): State<Arc<AppState>>,
+	Path(file): Path<String>,
+) -> ApiError<impl IntoResponse> {
+	let Some(version) = file.strip_suffix(".sqlite") else {
+		return Err((
+			StatusCode::NOT_FOUND,
+			format!("Unknown export {file}, expected <version>.sqlite"),
+		));
+	};
+	let version = state.vromf_cache.resolve_version(Some(version))?;
+	let path = export::sqlite(state.clone(), version).await?;
+	serve_file(
+		&path,
+		"application/vnd.sqlite3",
+		&format!("{version}.sqlite"),
+	)
+	.await
+}
+
+#[utoipa::path(
+	get,
+	path = "/export/{version}/parquet",
+	params(
+		("version" = String, description = "Either version string or literal \"latest\"", example = "latest"),
+	),
+	responses(
+        (status = 200, description = "Zip of the Parquet tables blk_values, units and weapons", content_type = ["application/zip"]),
+		(status = 400, description = "Version invalid"),
+	)
+)]
+pub async fn export_parquet(
+	State(state): State<Arc<AppState>>,
+	Path(version): Path<String>,
+) -> ApiError<impl IntoResponse> {
+	let version = state.vromf_cache.resolve_version(Some(&version))?;
+	let path = export::parquet(state.clone(), version).await?;
+	serve_file(&path, "application/zip", &format!("{version}.parquet.zip")).await
+}
+
+/// Streams an export from disk
+async fn serve_file(path: &StdPath, content_type: &str, file_name: &str) -> ApiError<Response> {
+	let file = tokio::fs::File::open(path).await.convert_err()?;
+	let len = file.metadata().await.convert_err()?.len();
+	Response::builder()
+		.header("Content-Type", content_type)
+		.header("Content-Length", len)
+		.header(
+			"Content-Disposition",
+			format!("attachment; filename=\"{file_name}\""),
+		)
+		.body(Body::from_stream(ReaderStream::new(file)))
+		.convert_err()
+}
@@ -0,0 +1,21 @@
+use std::sync::Arc;
+
+use axum::{extract::State, response::IntoResponse};
+use http::header::CONTENT_TYPE;
+
+use crate::{app_state::AppState, error::ApiError, feed};
+
+#[utoipa::path(
+	get,
+	path = "/feed.atom",
+	responses(
+        (status = 200, description = "Atom feed of the most recent versions with a summary of their changed files", content_type = ["application/atom+xml"]),
+	)
+)]
+pub async fn atom_feed(State(state): State<Arc<AppState>>) -> ApiError<impl IntoResponse> {
+	let feed = feed::atom(state).await?;
+	Ok((
+		[(CONTENT_TYPE, "application/atom+xml; charset=utf-8")],
+		feed,
+	))
+}
@@ -1,46 +1,80 @@
-use std::{path::Path as StdPath, str::FromStr, sync::Arc};
+use std::{
+	borrow::Cow,
+	io,
+	io::Write,
+	path::Path as StdPath,
+	str::FromStr,
+	sync::Arc,
+	task::{ready, Poll},
+};
 
 use axum::{
-	body::Body,
+	body::{Body, Bytes},
 	extract::{Path, Query, State},
 	response::{IntoResponse, Response},
 };
 use dashmap::DashMap;
+use futures::stream;
 use http::StatusCode;
-use serde::Deserialize;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use strum::VariantArray;
+use tokio::{sync::mpsc, task::spawn_blocking};
+use tracing::warn;
 use utoipa::{IntoParams, ToSchema};
-use wt_blk::vromf::{BlkOutputFormat, File, VromfUnpacker, ZipFormat};
+use wt_blk::vromf::{BlkOutputFormat, File, VromfUnpacker};
 use wt_version::Version;
 
 use crate::{
 	app_state::AppState,
-	endpoints::get_vromfs::fetch_vromf,
+	archive::{ArchiveFormat, ArchiveWriter, ChannelWriter},
+	endpoints::{get_vromfs::fetch_vromf, search::PathPattern},
 	error::ApiError,
 	eyre_error_translation::{EyreToApiError, OptionToApiError},
+	flatten,
+	output_format::{is_blk, OutputFormat},
 	vromf_enum::VromfType,
 };
 
+/// Amount of files unpacked in parallel before they are written to an archive
+const UNPACK_BATCH_SIZE: usize = 64;
+/// Amount of archive chunks buffered for slow clients
+const STREAM_CHANNEL_CAPACITY: usize = 16;
+/// Larger responses are never kept in the files cache
+const MAX_CACHED_SIZE: usize = 8 * 1024 * 1024;
+
 pub struct UnpackedVromfs {
-	unpackers: DashMap<(Version, VromfType), VromfUnpacker>,
+	// Shared, so that long running readers do not hold a lock on the map
+	unpackers: DashMap<(Version, VromfType), Arc<VromfUnpacker>>,
+	// Listing of all raw files per VROMF, built lazily for searches and filters
+	indices:   DashMap<(Version, VromfType), Arc<Vec<FileEntry>>>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FileEntry {
+	/// Path of the file within its vromf
+	pub path: String,
+	/// Size of the raw (undecoded) file in bytes
+	pub size: usize,
 }
 
 impl UnpackedVromfs {
 	pub async fn unpack_one(state: Arc<AppState>, req: Arc<FileRequest>) -> ApiError<Vec<u8>> {
-		Self::cache_unpacker(&state.unpacked_vromfs, state.clone(), &req).await?;
+		Self::cache_unpacker(state.clone(), req.version, req.vromf).await?;
 
 		let vromf = req.vromf;
 		let res = state
 			.clone()
 			.spawn_worker(move |s| {
 				let res = || {
-					let unpacker = state
-						.unpacked_vromfs
-						.unpackers
-						.get(&(req.version, vromf))
-						.convert_err("cache unpacker did not insert requested vromf")?;
+					let unpacker = state.unpacked_vromfs.unpacker(req.version, vromf)?;
 
-					let res = unpacker.unpack_one(StdPath::new(&req.path), req.unpack_format, true);
+					let res = unpacker.unpack_one(
+						StdPath::new(&req.path),
+						req.format.unpack_format(),
+						true,
+					);
 					if let Err(e) = &res {
 						// TODO: patch wt_blk so that this works via type downcasting
 						let cause = e.root_cause().to_string();
@@ -49,71 +83,323 @@ impl UnpackedVromfs {
 						}
 					}
 
-					let res = res.convert_err()?;
-					Ok(res)
+					let (_, buf) = res.convert_err()?.split();
+					match &req.select {
+						None => req.format.convert(&req.path, buf),
+						Some(select) => {
+							let value = serde_json::from_slice::<Value>(&buf).convert_err()?;
+							let selected = flatten::select(&value, select).ok_or_else(|| {
+								(
+									StatusCode::NOT_FOUND,
+									format!("Key path {select} not found in {}", req.path),
+								)
+							})?;
+							req.format
+								.serialize(selected)
+								.map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))
+						},
+					}
 				};
-				s.send(res()).expect("channel to remain open after work");
+				let _ = s.send(res());
 			})
 			.await??;
 
-		Ok(res.split().1)
+		Ok(res)
 	}
 
-	pub async fn unpack_zip(state: Arc<AppState>, req: Arc<FileRequest>) -> ApiError<Vec<u8>> {
-		Self::cache_unpacker(&state.unpacked_vromfs, state.clone(), &req).await?;
+	/// Streams a folder as archive, writing entries while they are being unpacked
+	pub async fn stream_archive(
+		state: Arc<AppState>,
+		req: Arc<FileRequest>,
+	) -> ApiError<mpsc::Receiver<io::Result<Bytes>>> {
+		let paths = Self::select_folder(state.clone(), &req).await?;
 
-		let vromf = req.vromf;
-		let res = state
-			.clone()
-			.spawn_worker(move |s| {
-				let res = || {
-					let unpacker = state
-						.unpacked_vromfs
-						.unpackers
-						.get(&(req.version, vromf))
-						.convert_err("cache unpacker did not insert requested vromf")?;
-
-					let res = unpacker
-						.unpack_subfolder_to_zip(
-							&req.path,
-							true,
-							ZipFormat::Uncompressed,
-							req.unpack_format,
-							true,
-							true, // TODO: Set this false when the system is under very high load
-						)
-						.convert_err();
-					Ok(res)
+		let unpacker = state.unpacked_vromfs.unpacker(req.version, req.vromf)?;
+		let (sender, receiver) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+		// Writing blocks while the client is slow to receive, so it happens outside the worker pool,
+		// which only unpacks the batches in between
+		spawn_blocking(move || {
+			let res = || {
+				let writer = ChannelWriter::new(sender.clone());
+				if req.format == OutputFormat::Flat {
+					return Self::write_flat_csv(&state, writer, &unpacker, &req, &paths);
+				}
+
+				let mut archive = ArchiveWriter::new(writer, req.archive).convert_err()?;
+				Self::for_each_unpacked(
+					&state,
+					&unpacker,
+					&req,
+					req.format,
+					&paths,
+					|path, buf| archive.append(path, &buf).convert_err(),
+				)?;
+				archive.finish().convert_err()?.flush().convert_err()
+			};
+			if let Err((_, e)) = res() {
+				warn!("Aborted streaming archive. Reason: {e}");
+				ChannelWriter::new(sender).fail(io::Error::other(e));
+			}
+		});
+
+		Ok(receiver)
+	}
+
+	/// Writes every leaf of the selected BLKs as one `path,key,value` CSV
+	fn write_flat_csv(
+		state: &AppState,
+		writer: ChannelWriter,
+		unpacker: &VromfUnpacker,
+		req: &FileRequest,
+		paths: &[String],
+	) -> ApiError<()> {
+		let mut csv = csv::Writer::from_writer(writer);
+		csv.write_record(["path", "key", "value"]).convert_err()?;
+		Self::for_each_unpacked(
+			state,
+			unpacker,
+			req,
+			OutputFormat::Json,
+			paths,
+			|path, buf| {
+				if !is_blk(path) {
+					return Ok(());
+				}
+				// BLKs which failed to decode are returned raw, and have no keys to list
+				let Ok(value) = serde_json::from_slice::<Value>(&buf) else {
+					return Ok(());
 				};
-				s.send(res()).expect("channel to remain open after work");
+				let mut res = Ok(());
+				flatten::visit_leaves(&value, &mut |key_path, leaf| {
+					if res.is_ok() {
+						res = csv.write_record([path, key_path, &*flatten::leaf_to_string(leaf)]);
+					}
+				});
+				res.convert_err()
+			},
+		)?;
+		csv.into_inner()
+			.map_err(|e| e.into_error())
+			.convert_err()?
+			.flush()
+			.convert_err()
+	}
+
+	/// Unpacks the selected files in parallel batches on the worker pool, handing them to `f` in order with their output path
+	fn for_each_unpacked(
+		state: &AppState,
+		unpacker: &VromfUnpacker,
+		req: &FileRequest,
+		format: OutputFormat,
+		paths: &[String],
+		mut f: impl FnMut(&str, Vec<u8>) -> ApiError<()>,
+	) -> ApiError<()> {
+		let mut written = 0;
+		for batch in paths.chunks(UNPACK_BATCH_SIZE) {
+			let bufs = state.install(|| {
+				batch
+					.par_iter()
+					.map(|path| {
+						let raw = || -> ApiError<Vec<u8>> {
+							Ok(unpacker
+								.unpack_one(StdPath::new(path), None, false)
+								.convert_err()?
+								.split()
+								.1)
+						};
+						match unpacker.unpack_one(StdPath::new(path), format.unpack_format(), true)
+						{
+							// Files which cannot be converted are kept as they are, like undecodable files
+							Ok(file) => Ok(match format.convert(path, file.split().1) {
+								Ok(buf) => (format.output_path(path), buf),
+								Err(_) => (Cow::Borrowed(path.as_str()), raw()?),
+							}),
+							// Files which fail to decode are returned raw instead of failing the entire folder
+							Err(_) => Ok((Cow::Borrowed(path.as_str()), raw()?)),
+						}
+					})
+					.collect::<ApiError<Vec<_>>>()
+			})?;
+
+			for (path, buf) in bufs {
+				written += buf.len();
+				// Surfaces as an aborted stream, as the response has already started
+				if let Some(max) = req.filter.max_bytes.filter(|max| written > *max) {
+					return Err((
+						StatusCode::PAYLOAD_TOO_LARGE,
+						format!("Folder exceeds max_bytes of {max}"),
+					));
+				}
+				f(&path, buf)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Selects the paths of all files in the requested folder which pass its filters
+	pub async fn select_folder(state: Arc<AppState>, req: &FileRequest) -> ApiError<Vec<String>> {
+		let index = Self::file_index(state, req.version, req.vromf).await?;
+		let folder = req.path.trim_matches('/');
+		let include = req
+			.filter
+			.include
+			.as_deref()
+			.map(|glob| PathPattern::new(glob, None))
+			.transpose()?;
+		let exclude = req
+			.filter
+			.exclude
+			.as_deref()
+			.map(|glob| PathPattern::new(glob, None))
+			.transpose()?;
+
+		let mut in_folder = false;
+		let mut paths = vec![];
+		let mut raw_size = 0;
+		for entry in index.iter() {
+			let relative = if folder.is_empty() {
+				entry.path.as_str()
+			} else {
+				match entry.path.strip_prefix(folder) {
+					Some(rest) if rest.starts_with('/') => &rest[1..],
+					_ => continue,
+				}
+			};
+			in_folder = true;
+
+			if include.as_ref().is_some_and(|g| !g.is_match(relative))
+				|| exclude.as_ref().is_some_and(|g| g.is_match(relative))
+			{
+				continue;
+			}
+			paths.push(entry.path.clone());
+			raw_size += entry.size;
+		}
+
+		if !in_folder {
+			return Err((
+				StatusCode::NOT_FOUND,
+				format!("Folder {} not found in {}", req.path, req.vromf),
+			));
+		}
+		if let Some(max) = req.filter.max_files {
+			if paths.len() > max {
+				return Err((
+					StatusCode::PAYLOAD_TOO_LARGE,
+					format!(
+						"Folder contains {} matching files which exceeds max_files of {max}",
+						paths.len()
+					),
+				));
+			}
+		}
+		// Raw sizes are exact, converted sizes are only known once unpacked and checked while streaming
+		if let Some(max) = req.filter.max_bytes {
+			if req.format == OutputFormat::Raw && raw_size > max {
+				return Err((
+					StatusCode::PAYLOAD_TOO_LARGE,
+					format!("Folder contains {raw_size} bytes which exceeds max_bytes of {max}"),
+				));
+			}
+		}
+		Ok(paths)
+	}
+
+	/// Returns every file contained in a vromf, sorted by path
+	pub async fn file_index(
+		state: Arc<AppState>,
+		version: Version,
+		vromf: VromfType,
+	) -> ApiError<Arc<Vec<FileEntry>>> {
+		if let Some(index) = state.unpacked_vromfs.indices.get(&(version, vromf)) {
+			return Ok(index.clone());
+		}
+		Self::cache_unpacker(state.clone(), version, vromf).await?;
+
+		// Read from the directory of the vromf, without copying or decoding any file
+		let mut index = state
+			.unpacked_vromfs
+			.unpacker(version, vromf)?
+			.files()
+			.iter()
+			.map(|file| FileEntry {
+				path: file.path().to_string_lossy().replace('\\', "/"),
+				size: file.buf().len(),
 			})
-			.await???;
+			.collect::<Vec<_>>();
+		index.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+		let index = Arc::new(index);
 
-		Ok(res)
+		state
+			.unpacked_vromfs
+			.indices
+			.insert((version, vromf), index.clone());
+		Ok(index)
+	}
+
+	/// Unpacks every file of a cached unpacker, must be called from the worker pool
+	pub fn unpack_all(
+		state: &AppState,
+		version: Version,
+		vromf: VromfType,
+		format: Option<BlkOutputFormat>,
+		apply_overrides: bool,
+	) -> ApiError<Vec<File>> {
+		let unpacker = state.unpacked_vromfs.unpacker(version, vromf)?;
+		unpacker.unpack_all(format, apply_overrides).convert_err()
+	}
+
+	/// Unpacks the files of a cached vromf one after another, so that only one of them is held in memory.
+	/// Files which fail to decode are handed over raw, must be called from the worker pool
+	pub fn for_each_file(
+		state: &AppState,
+		version: Version,
+		vromf: VromfType,
+		format: Option<BlkOutputFormat>,
+		mut f: impl FnMut(&str, Vec<u8>) -> ApiError<()>,
+	) -> ApiError<()> {
+		let unpacker = state.unpacked_vromfs.unpacker(version, vromf)?;
+		for file in unpacker.files() {
+			let buf = match unpacker.unpack_one(file.path(), format, true) {
+				Ok(file) => file.split().1,
+				Err(_) => file.buf().to_vec(),
+			};
+			f(&file.path().to_string_lossy().replace('\\', "/"), buf)?;
+		}
+		Ok(())
 	}
 
-	/// Ensures that unpacker is cached
+	/// Returns the unpacker of a vromf, which has to be cached beforehand
+	pub fn unpacker(&self, version: Version, vromf: VromfType) -> ApiError<Arc<VromfUnpacker>> {
+		self.unpackers
+			.get(&(version, vromf))
+			.map(|unpacker| unpacker.clone())
+			.convert_err("cache unpacker did not insert requested vromf")
+	}
+
+	/// Ensures that the unpackers for all vromfs of a version are cached
 	pub async fn cache_unpacker(
-		&self,
 		state: Arc<AppState>,
-		req: &FileRequest,
+		version: Version,
+		vromf: VromfType,
 	) -> Result<(), (StatusCode, String)> {
 		if state
 			.unpacked_vromfs
 			.unpackers
-			.contains_key(&(req.version, req.vromf))
+			.contains_key(&(version, vromf))
 		{
 			return Ok(());
 		}
 
 		let mut ask_api = true;
 		for vromf in VromfType::VARIANTS {
-			let buf =
-				fetch_vromf(state.clone(), Some(req.version), req.vromf, &mut ask_api).await?;
+			let buf = fetch_vromf(state.clone(), Some(version), *vromf, &mut ask_api).await?;
 			state.unpacked_vromfs.unpackers.insert(
-				(req.version, *vromf),
-				VromfUnpacker::from_file(&File::from_raw(vromf.into(), buf), false)
-					.convert_err()?,
+				(version, *vromf),
+				Arc::new(
+					VromfUnpacker::from_file(&File::from_raw(vromf.into(), buf), false)
+						.convert_err()?,
+				),
 			);
 		}
 		Ok(())
@@ -124,6 +410,7 @@ impl Default for UnpackedVromfs {
 	fn default() -> Self {
 		Self {
 			unpackers: Default::default(),
+			indices:   Default::default(),
 		}
 	}
 }
@@ -136,24 +423,60 @@ pub struct FileRequest {
 	/// File path within vromf to return
 	path: String,
 
-	/// None if raw file
-	unpack_format: Option<BlkOutputFormat>,
+	/// Format BLK files are converted to
+	format: OutputFormat,
 
 	/// Returns just one file directly, or a zip containing many requested files (folder)
 	single_file: bool,
 
 	/// Which vromf to get from
 	vromf: VromfType,
+
+	/// Selection within a folder, always empty for single files
+	filter: FolderFilter,
+
+	/// Archive the folder is returned as, always zip for single files
+	archive: ArchiveFormat,
+
+	/// Key path or JSON pointer into a single BLK
+	select: Option<String>,
+}
+
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Default)]
+pub struct FolderFilter {
+	include:   Option<String>,
+	exclude:   Option<String>,
+	max_files: Option<usize>,
+	max_bytes: Option<usize>,
 }
 
 #[derive(Debug, Deserialize, IntoParams)]
 pub struct Params {
 	#[param(example = "latest", default = "Latest available")]
 	/// Either version string or literal "latest"
-	version: Option<String>,
+	version:   Option<String>,
 	#[param(example = "json", default = "json")]
-	/// Format to convert BLK to. One of: [raw, blk, json]
-	format:  Option<String>,
+	/// Format to convert BLK to. One of: [raw, blk, json, yaml, toml, msgpack, cbor, flat, png]. Folders in flat format are returned as one CSV, png converts textures and leaves other files raw
+	format:    Option<String>,
+	#[param(example = "**/*.blk")]
+	/// Folders only: glob a file path relative to the folder has to match, `*` stays within a folder and `**` crosses them
+	include:   Option<String>,
+	#[param(example = "**/*.png")]
+	/// Folders only: glob excluding file paths relative to the folder
+	exclude:   Option<String>,
+	#[param(example = 1000)]
+	/// Folders only: fails with 413 when more files than this would be returned
+	max_files: Option<usize>,
+	#[param(example = 100000000)]
+	/// Folders only: fails with 413 when the unpacked files exceed this many bytes. Only raw output is checked before
+	/// streaming starts, converted output aborts the already started archive once it grows past the limit
+	max_bytes: Option<usize>,
+	#[param(example = "tar.zst", default = "zip")]
+	/// Folders only: archive format. One of: [zip, tar, tar.gz, tar.zst]
+	archive:   Option<String>,
+	#[param(example = "rocket/maxDistance")]
+	/// Single BLKs only: returns just the value at this JSON pointer or dotted key path (rocket.maxDistance)
+	select:    Option<String>,
 }
 
 impl FileRequest {
@@ -180,45 +503,69 @@ impl FileRequest {
 				Some(e) => (VromfType::from_str(e.0).convert_err()?, e.1.to_owned()),
 			}
 		};
-		let latest = state.vromf_cache.latest_known_version();
-		let unpack_format = match &query.format {
-			None => Some(BlkOutputFormat::Json),
-			Some(f) => match f.to_ascii_lowercase().as_str() {
-				"blk" => Some(BlkOutputFormat::BlkText),
-				"json" => Some(BlkOutputFormat::Json),
-				"raw" => None,
-				_ => {
-					return Err((
-						StatusCode::BAD_REQUEST,
-						format!("unknown output format: {f}"),
-					))
-				},
-			},
+		let format = match &query.format {
+			None => OutputFormat::Json,
+			Some(f) => OutputFormat::from_str(f).map_err(|_| {
+				(
+					StatusCode::BAD_REQUEST,
+					format!("unknown output format: {f}"),
+				)
+			})?,
 		};
 		let single_file = path.contains('.');
+		let archive = match &query.archive {
+			Some(a) if !single_file => ArchiveFormat::from_str(a).map_err(|_| {
+				(
+					StatusCode::BAD_REQUEST,
+					format!("unknown archive format: {a}"),
+				)
+			})?,
+			_ => ArchiveFormat::Zip,
+		};
+		let filter = if single_file {
+			FolderFilter::default()
+		} else {
+			// Validate early so that malformed globs never reach the worker pool
+			for glob in [&query.include, &query.exclude].into_iter().flatten() {
+				PathPattern::new(glob, None)?;
+			}
+			FolderFilter {
+				include:   query.include.clone(),
+				exclude:   query.exclude.clone(),
+				max_files: query.max_files,
+				max_bytes: query.max_bytes,
+			}
+		};
+
+		if query.select.is_some() {
+			if !single_file || !path.ends_with("blk") {
+				return Err((
+					StatusCode::BAD_REQUEST,
+					"select is only supported on single BLK files".to_owned(),
+				));
+			}
+			if matches!(
+				format,
+				OutputFormat::Raw | OutputFormat::Blk | OutputFormat::Png
+			) {
+				return Err((
+					StatusCode::BAD_REQUEST,
+					format!("select is not supported for format {format}"),
+				));
+			}
+		}
 
 		Ok(Self {
-			version: query
-				.version
-				.clone()
-				.filter(|v| v != "latest") // Latest string just gets turned into none
-				.map(|e| {
-					Version::from_str(&e).convert_err().map_err(|e| {
-						(
-							e.0,
-							format!(
-								"Invalid version: {}",
-								query.version.clone().unwrap_or_default()
-							),
-						)
-					})
-				})
-				.transpose()?
-				.unwrap_or(latest),
+			version: state
+				.vromf_cache
+				.resolve_version(query.version.as_deref())?,
 			path,
-			unpack_format,
+			format,
 			single_file,
 			vromf,
+			filter,
+			archive,
+			select: query.select.clone(),
 		})
 	}
 }
@@ -231,9 +578,11 @@ impl FileRequest {
 		Params
 	),
 	responses(
-        (status = 200, description = "Plaintext or binary depending on format and file", content_type = ["text/plain", "application/octet-stream"]),
-		(status = 404, description = "Provided path is not in vromf"),
-		(status = 400, description = "Format specifier invalid"),
+        (status = 200, description = "Plaintext or binary depending on format and file, folders are archived", content_type = ["text/plain", "application/octet-stream", "application/json", "application/yaml", "application/toml", "application/msgpack", "application/cbor", "text/csv", "application/zip", "application/x-tar", "application/gzip", "application/zstd", "image/png"]),
+		(status = 404, description = "Provided path is not in vromf, or selected key path is not in BLK"),
+		(status = 400, description = "Format specifier or glob invalid"),
+		(status = 422, description = "BLK cannot be represented in the requested format, or texture cannot be decoded"),
+		(status = 413, description = "Folder exceeds max_files, or max_bytes in raw format, or texture exceeds the image size limit. Converted folders exceeding max_bytes end in an aborted stream instead"),
 	)
 )]
 pub async fn get_files(
@@ -257,34 +606,67 @@ pub async fn get_files(
 	// From here on req gets passed to a bunch of threads so we share it
 	let req = Arc::new(req);
 
-	let (res, content_type) = if req.single_file {
-		let t = match &req.unpack_format {
-			None => "application/octet-stream",
-			Some(f) => {
-				if req.path.ends_with("blk") {
-					match f {
-						BlkOutputFormat::Json => "application/json",
-						BlkOutputFormat::BlkText => "text/plain",
-					}
-				} else {
-					"application/octet-stream"
-				}
-			},
+	if !req.single_file {
+		let content_type = if req.format == OutputFormat::Flat {
+			"text/csv"
+		} else {
+			req.archive.content_type()
 		};
-		let res = UnpackedVromfs::unpack_one(state.clone(), req.clone()).await?;
-		(res, t)
-	} else {
-		let res = UnpackedVromfs::unpack_zip(state.clone(), req.clone()).await?;
-		(res, "application/zip")
-	};
+		let receiver = UnpackedVromfs::stream_archive(state.clone(), req.clone()).await?;
+		return Ok(Response::builder()
+			.header("Content-Type", content_type)
+			.body(cache_stream(state, req, content_type, receiver))
+			.convert_err()?);
+	}
+
+	let t = req.format.content_type(&req.path);
+	let res = UnpackedVromfs::unpack_one(state.clone(), req.clone()).await?;
 
-	state
-		.files_cache
-		.insert(
-			Arc::<FileRequest>::unwrap_or_clone(req),
-			(res.clone(), content_type),
-		)
-		.await;
+	if res.len() <= MAX_CACHED_SIZE {
+		state
+			.files_cache
+			.insert(Arc::<FileRequest>::unwrap_or_clone(req), (res.clone(), t))
+			.await;
+	}
+
+	return_body((res, t))
+}
 
-	return_body((res, content_type))
+/// Forwards a streamed archive to the client, caching it once complete unless it grew too large
+fn cache_stream(
+	state: Arc<AppState>,
+	req: Arc<FileRequest>,
+	content_type: &'static str,
+	mut receiver: mpsc::Receiver<io::Result<Bytes>>,
+) -> Body {
+	let mut cached = Some((req, vec![]));
+	Body::from_stream(stream::poll_fn(move |cx| {
+		let res = ready!(receiver.poll_recv(cx));
+		match &res {
+			Some(Ok(chunk)) => {
+				if cached
+					.as_ref()
+					.is_some_and(|(_, buf)| buf.len() + chunk.len() > MAX_CACHED_SIZE)
+				{
+					cached = None;
+				}
+				if let Some((_, buf)) = &mut cached {
+					buf.extend_from_slice(chunk);
+				}
+			},
+			Some(Err(_)) => cached = None,
+			None => {
+				if let Some((req, buf)) = cached.take() {
+					let state = state.clone();
+					tokio::spawn(async move {
+						state
+							.files_cache
+							.insert(Arc::unwrap_or_clone(req), (buf, content_type))
+							.await;
+					});
+				}
+			},
+		}
+		Poll::Ready(res)
+	}))
 }
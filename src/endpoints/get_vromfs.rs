@@ -1,22 +1,35 @@
 use std::{
-	collections::HashMap,
+	collections::{HashMap, HashSet},
 	env,
 	env::current_exe,
+	fs::{self, OpenOptions},
+	io::{self, Write},
+	mem,
 	num::NonZeroUsize,
+	path::{Path as StdPath, PathBuf},
 	str::FromStr,
-	sync::{Arc, OnceLock},
+	sync::{Arc, Mutex as StdMutex, OnceLock},
 	time::Duration,
 };
 
 use arc_swap::ArcSwap;
 use axum::extract::{Path, State};
-use dashmap::{mapref::multiple::RefMulti, DashMap};
+use dashmap::{
+	mapref::{entry::Entry, multiple::RefMulti},
+	DashMap,
+};
+use futures::{stream, StreamExt, TryStreamExt};
 use http::StatusCode;
 use moka::ops::compute::Op;
-use octocrab::Octocrab;
+use octocrab::{
+	models::repos::{ContentItems, RepoCommit},
+	Page,
+};
+use sha1::{Digest, Sha1};
 use strum::VariantArray;
 use tokio::{
-	sync::{oneshot::Sender, RwLock},
+	sync::{oneshot::Sender, Mutex, RwLock},
+	task::spawn_blocking,
 	time::sleep,
 };
 use tracing::{debug, error, info, warn};
@@ -24,33 +37,91 @@ use wt_version::Version;
 
 use crate::{
 	app_state::AppState,
+	content_index::ContentIndex,
 	error::ApiError,
+	events::{self, VersionEvent},
 	eyre_error_translation::{EyreToApiError, OptionToApiError},
+	rate_limit::Priority,
 	vromf_enum::VromfType,
 };
 
+pub const DATAMINE_REPO: &str = "gszabi99/War-Thunder-Datamine";
+/// Vromfs downloaded at the same time
+const DOWNLOAD_PARALLELISM: usize = 4;
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(300);
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+/// Doubled after every failed attempt
+const DOWNLOAD_BACKOFF: Duration = Duration::from_secs(2);
+
 pub struct VromfCache {
-	elems:        DashMap<Version, HashMap<VromfType, Vec<u8>>>,
-	commit_pages: DashMap<Version, String>,
+	elems:          DashMap<Version, HashMap<VromfType, Vec<u8>>>,
+	commit_pages:   DashMap<Version, String>,
+	// Git blob SHAs of the vromfs per version, to tell which changed without downloading them
+	blob_shas:      DashMap<Version, HashMap<VromfType, String>>,
+	download_locks: DashMap<Version, Arc<Mutex<()>>>,
+	// Latest version published as event, which lookups mapping newer commits do not advance
+	announced:      StdMutex<Version>,
+	// On-disk index of commits discovered at runtime
+	commit_index:   PathBuf,
 }
 
 impl Default for VromfCache {
 	fn default() -> Self {
-		Self {
-			elems:        DashMap::new(),
-			commit_pages: cached_shas(),
-		}
+		Self::new(commit_index_path())
 	}
 }
 
 impl VromfCache {
+	/// Knows the embedded commits and those which previous runs discovered and wrote to `commit_index`
+	pub fn new(commit_index: PathBuf) -> Self {
+		let commit_pages = cached_shas(&commit_index);
+		let announced = commit_pages.iter().map(|e| *e.key()).max().unwrap();
+		Self {
+			elems: DashMap::new(),
+			commit_pages,
+			blob_shas: DashMap::new(),
+			download_locks: DashMap::new(),
+			announced: StdMutex::new(announced),
+			commit_index,
+		}
+	}
+
 	pub fn latest_known_version(&self) -> Version {
 		self.list_versions().map(|e| *e.key()).max().unwrap()
 	}
 
+	/// Latest version published as event
+	pub fn announced(&self) -> Version {
+		*self.announced.lock().unwrap(/*fine*/)
+	}
+
+	/// Marks a version as published, returning false if it or a newer one already was
+	fn announce(&self, version: Version) -> bool {
+		let mut announced = self.announced.lock().unwrap(/*fine*/);
+		if version <= *announced {
+			return false;
+		}
+		*announced = version;
+		true
+	}
+
+	/// Whether all vromfs of a version are downloaded
+	pub fn is_cached(&self, version: Version) -> bool {
+		self.elems.contains_key(&version)
+	}
+
 	pub fn list_versions(&self) -> impl Iterator<Item = RefMulti<'_, Version, String>> {
 		self.commit_pages.iter()
 	}
+
+	/// Parses a user provided version, where none or the literal "latest" resolve to the latest known version
+	pub fn resolve_version(&self, version: Option<&str>) -> ApiError<Version> {
+		match version.filter(|v| *v != "latest") {
+			None => Ok(self.latest_known_version()),
+			Some(v) => Version::from_str(v)
+				.map_err(|_| (StatusCode::BAD_REQUEST, format!("Invalid version: {v}"))),
+		}
+	}
 }
 
 pub async fn fetch_vromf(
@@ -76,7 +147,7 @@ pub async fn fetch_vromf(
 
 	// Only refresh when necessary
 	if *ask_api {
-		pull_vromf_to_cache(state.clone(), Some(version)).await?;
+		pull_vromf_to_cache(state.clone(), Some(version), Priority::User).await?;
 	}
 
 	let res = state
@@ -111,15 +182,26 @@ pub async fn get_latest(
 pub async fn pull_vromf_to_cache(
 	state: Arc<AppState>,
 	mut version: Option<Version>,
+	priority: Priority,
 ) -> ApiError<()> {
 	info!("Refreshing vromf cache");
 
-	let mut octo = state.octocrab.lock().await;
 	let get_latest = version.is_none();
-	let sha = find_version_sha(state.clone(), &mut version, &mut octo, Some(2)).await?;
+	// Other crawls may have mapped newer versions already, so only the announced one counts as previous
+	let previous = state.vromf_cache.announced();
+	let sha = find_version_sha(state.clone(), &mut version, priority, Some(2)).await?;
 	let version = version.convert_err("Version was not set by find_version_sha")?;
+	// Held until the version is cached, so that concurrent requests download it only once
+	let download_lock = state
+		.vromf_cache
+		.download_locks
+		.entry(version)
+		.or_default()
+		.clone();
+	let _download = download_lock.lock().await;
+	let mut event = None;
 	if get_latest {
-		if version > state.vromf_cache.latest_known_version() {
+		if version > previous {
 			info!("Found newer version: {version}");
 
 			#[cfg(feature = "dev-cache")]
@@ -146,10 +228,11 @@ pub async fn pull_vromf_to_cache(
 						.vromf_cache
 						.elems
 						.insert(version, HashMap::from_iter(files.into_iter()));
+					ContentIndex::schedule_build(state.clone(), version);
 					return Ok(());
 				}
 			}
-			let vromfs = get_vromfs(&sha, &mut octo).await?;
+			let vromfs = get_vromfs(&state, version, &sha, priority).await?;
 			state.vromf_cache.elems.insert(version, vromfs);
 
 			#[cfg(feature = "dev-cache")]
@@ -175,64 +258,233 @@ pub async fn pull_vromf_to_cache(
 			}
 
 			info!("Pushed {version} to cache");
+			let changed = changed_vromfs(&state, version, previous, priority)
+				.await
+				.unwrap_or_else(|e| {
+					warn!(
+						"Failed to compare {version} with {previous}, reporting every vromf as changed. Reason: {}",
+						e.1
+					);
+					VromfType::VARIANTS.to_vec()
+				});
+			event = Some(VersionEvent::new(version, sha.clone(), changed));
 		} else {
 			info!("No newer version found");
 		}
 	} else {
 		if state.vromf_cache.elems.get(&version).is_none() {
-			let vromfs = get_vromfs(&sha, &mut octo).await?;
+			let vromfs = get_vromfs(&state, version, &sha, priority).await?;
 			state.vromf_cache.elems.insert(version, vromfs);
 		}
 	}
 	state.vromf_cache.commit_pages.insert(version, sha);
+	// Older versions are only indexed once their content is searched
+	if version == state.vromf_cache.latest_known_version()
+		&& state.vromf_cache.elems.contains_key(&version)
+	{
+		ContentIndex::schedule_build(state.clone(), version);
+	}
+	// Only published once the version can be requested, and by one of concurrent refreshes
+	if let Some(event) = event {
+		if state.vromf_cache.announce(version) {
+			events::publish(&state, event);
+		}
+	}
 	Ok(())
 }
 
-async fn get_vromfs(sha: &str, octo: &mut Octocrab) -> ApiError<HashMap<VromfType, Vec<u8>>> {
-	let mut reqs = HashMap::new();
+/// Downloads a single vromf of a version without caching it, for when only few of its files are needed
+pub async fn download_single_vromf(
+	state: Arc<AppState>,
+	version: Version,
+	vromf: VromfType,
+	priority: Priority,
+) -> ApiError<Vec<u8>> {
+	let sha = find_version_sha(state.clone(), &mut Some(version), priority, Some(2)).await?;
+	let metadata = vromf_metadata(&state, &sha, vromf, priority).await?;
+	let (_, buf) = download_vromf(&reqwest::Client::new(), metadata).await?;
+	Ok(buf)
+}
+
+async fn get_vromfs(
+	state: &AppState,
+	version: Version,
+	sha: &str,
+	priority: Priority,
+) -> ApiError<HashMap<VromfType, Vec<u8>>> {
 	info!("Downloading vromfs from: {sha}");
-	for vromf in VromfType::VARIANTS {
-		let file = octo
-			.repos("gszabi99", "War-Thunder-Datamine")
-			.get_content()
-			.path(&format!("raw/{vromf}"))
-			.r#ref(sha)
-			.send()
-			.await
-			.convert_err()?;
-
-		let dec = reqwest::get(
-			file.items
-				.first()
-				.convert_err("commit has no elements")?
-				.clone()
-				.download_url
-				.convert_err("no download URL on commit")?,
-		)
+	let metadata = all_vromf_metadata(state, sha, priority).await?;
+	state.vromf_cache.blob_shas.insert(
+		version,
+		metadata.iter().map(|m| (m.vromf, m.sha.clone())).collect(),
+	);
+
+	let client = reqwest::Client::new();
+	stream::iter(metadata)
+		.map(|metadata| download_vromf(&client, metadata))
+		.buffer_unordered(DOWNLOAD_PARALLELISM)
+		.try_collect()
 		.await
-		.convert_err()?
+}
+
+/// Vromfs whose contents differ between two versions, going by their blob SHAs
+async fn changed_vromfs(
+	state: &AppState,
+	version: Version,
+	previous: Version,
+	priority: Priority,
+) -> ApiError<Vec<VromfType>> {
+	if !state.vromf_cache.blob_shas.contains_key(&previous) {
+		let sha = state
+			.vromf_cache
+			.commit_pages
+			.get(&previous)
+			.convert_err("previous version has no commit")?
+			.clone();
+		let metadata = all_vromf_metadata(state, &sha, priority).await?;
+		state.vromf_cache.blob_shas.insert(
+			previous,
+			metadata.into_iter().map(|m| (m.vromf, m.sha)).collect(),
+		);
+	}
+
+	let blob_shas = &state.vromf_cache.blob_shas;
+	let new = blob_shas
+		.get(&version)
+		.convert_err("blob SHAs of the new version are unknown")?;
+	let old = blob_shas
+		.get(&previous)
+		.convert_err("blob SHAs of the previous version are unknown")?;
+	Ok(VromfType::VARIANTS
+		.iter()
+		.filter(|vromf| new.get(*vromf) != old.get(*vromf))
+		.copied()
+		.collect())
+}
+
+async fn all_vromf_metadata(
+	state: &AppState,
+	sha: &str,
+	priority: Priority,
+) -> ApiError<Vec<VromfMetadata>> {
+	stream::iter(VromfType::VARIANTS)
+		.map(|vromf| vromf_metadata(state, sha, *vromf, priority))
+		.buffer_unordered(DOWNLOAD_PARALLELISM)
+		.try_collect()
+		.await
+}
+
+/// Where to download a vromf from, and what to expect
+struct VromfMetadata {
+	vromf:        VromfType,
+	download_url: String,
+	size:         usize,
+	/// Git blob SHA1 of the file
+	sha:          String,
+}
+
+async fn vromf_metadata(
+	state: &AppState,
+	sha: &str,
+	vromf: VromfType,
+	priority: Priority,
+) -> ApiError<VromfMetadata> {
+	let octo = &state.octocrab;
+	let route = format!("/repos/{DATAMINE_REPO}/contents/raw/{vromf}?ref={sha}");
+	let file = state
+		.rate_limit
+		.call::<ContentItems>(octo, priority, &route)
+		.await?
+		.items
+		.into_iter()
+		.next()
+		.convert_err("commit has no elements")?;
+
+	Ok(VromfMetadata {
+		vromf,
+		download_url: file.download_url.convert_err("no download URL on commit")?,
+		size: usize::try_from(file.size).convert_err()?,
+		sha: file.sha,
+	})
+}
+
+/// Downloads and verifies a vromf, retrying failed attempts on their own
+async fn download_vromf(
+	client: &reqwest::Client,
+	metadata: VromfMetadata,
+) -> ApiError<(VromfType, Vec<u8>)> {
+	let vromf = metadata.vromf;
+	let mut backoff = DOWNLOAD_BACKOFF;
+	let mut attempt = 1;
+	loop {
+		match try_download(client, &metadata).await {
+			Ok(buf) => return Ok((vromf, buf)),
+			Err(e) if attempt < DOWNLOAD_ATTEMPTS => {
+				warn!("Download of {vromf} failed on attempt {attempt}, retrying. Reason: {e}");
+				sleep(backoff).await;
+				backoff *= 2;
+				attempt += 1;
+			},
+			Err(e) => {
+				return Err((
+					StatusCode::BAD_GATEWAY,
+					format!("Failed to download {vromf} after {attempt} attempts: {e}"),
+				));
+			},
+		}
+	}
+}
+
+async fn try_download(
+	client: &reqwest::Client,
+	metadata: &VromfMetadata,
+) -> Result<Vec<u8>, String> {
+	let buf = client
+		.get(&metadata.download_url)
+		.timeout(DOWNLOAD_TIMEOUT)
+		.send()
+		.await
+		.and_then(|res| res.error_for_status())
+		.map_err(|e| e.to_string())?
 		.bytes()
 		.await
-		.convert_err()?
-		.to_vec();
-		reqs.insert(*vromf, dec);
+		.map_err(|e| e.to_string())?;
+
+	if buf.len() != metadata.size {
+		return Err(format!(
+			"expected {} bytes, received {}",
+			metadata.size,
+			buf.len()
+		));
+	}
+	let sha = git_blob_sha(&buf);
+	if sha != metadata.sha {
+		return Err(format!("expected blob {}, received {sha}", metadata.sha));
 	}
-	Ok(reqs)
+	Ok(buf.to_vec())
+}
+
+/// SHA1 git identifies a file's contents by
+fn git_blob_sha(buf: &[u8]) -> String {
+	let hash = Sha1::new()
+		.chain_update(format!("blob {}\0", buf.len()))
+		.chain_update(buf)
+		.finalize();
+	hex::encode(hash)
 }
 
 pub async fn find_version_sha(
 	state: Arc<AppState>,
 	v: &mut Option<Version>,
-	octo: &mut Octocrab,
+	priority: Priority,
 	// Set to none when performing unbounded cache warmup
 	maximum_pages_request_limit: Option<u64>,
 ) -> ApiError<String> {
 	let cache = &state.vromf_cache;
 	let latest_known_version = cache.latest_known_version();
 
-	// Consult LUT for ancient vromfs
-	if let Some(res) = cache.commit_pages.get(&v.unwrap_or(latest_known_version)) {
-		*v = Some(*res.key());
+	// Consult LUT for ancient vromfs, the latest version is always looked up on GitHub
+	if let Some(res) = v.and_then(|v| cache.commit_pages.get(&v)) {
 		return Ok(res.clone());
 	}
 
@@ -245,46 +497,76 @@ pub async fn find_version_sha(
 	// Else we look for newer versions than we currently know
 
 	let mut checks = 0;
+	// Versions first mapped by this crawl, which may still be claimed by an older commit
+	let mut discovered = HashSet::new();
+	let mut found = None;
+	// Sha of the latest statically known version, once an unbounded crawl reaches it
+	let mut reached = None;
+	// Written to the commit index once per page
+	let mut unpersisted = vec![];
 	info!("Fetching SHAs from github for version: {v:?}");
+	let octo = &state.octocrab;
 	'outer: for page in 1_u32.. {
-		let res = octo
-			.repos("gszabi99", "War-Thunder-Datamine")
-			.list_commits()
-			.page(page)
-			.send()
-			.await
-			.convert_err()?;
+		persist_commits(&state.vromf_cache.commit_index, mem::take(&mut unpersisted)).await;
+		let res = state
+			.rate_limit
+			.call::<Page<RepoCommit>>(
+				octo,
+				priority,
+				&format!("/repos/{DATAMINE_REPO}/commits?page={page}"),
+			)
+			.await?;
 		let commit_pages = &state.vromf_cache.commit_pages;
 
 		for commit in res {
-			let parsed = Version::from_str(&commit.commit.message).convert_err()?;
-			let before = commit_pages.insert(parsed, commit.sha.clone());
-			if before.is_none() {
-				warn!("discovered {parsed}");
+			let Some(parsed) = parse_commit_version(&commit.commit.message) else {
+				info!(
+					"Skipping commit {} without a version: {:?}",
+					commit.sha,
+					commit.commit.message.lines().next().unwrap_or_default()
+				);
+				continue;
+			};
+			match commit_pages.entry(parsed) {
+				Entry::Vacant(e) => {
+					warn!("discovered {parsed}");
+					unpersisted.push((parsed, commit.sha.clone()));
+					discovered.insert(parsed);
+					e.insert(commit.sha.clone());
+				},
+				// Commits are listed newest first, so of several uploads of one version the oldest wins
+				Entry::Occupied(mut e)
+					if discovered.contains(&parsed) && *e.get() != commit.sha =>
+				{
+					warn!(
+						"{parsed} was uploaded again, keeping the older commit {}",
+						commit.sha
+					);
+					unpersisted.push((parsed, commit.sha.clone()));
+					e.insert(commit.sha.clone());
+				},
+				Entry::Occupied(_) => {},
 			}
 
-			// If a specific version is desired, then check if we found it
-			if let Some(v) = *v {
-				if v == parsed {
-					return Ok(commit.sha);
-				}
-			}
-			// Otherwise just return whatever is the latest
-			else {
+			// Either the desired version, or whatever is the latest if none is desired.
+			// The rest of the page is still read, as uploads of the same version are listed next to each other
+			if found.is_none() && v.map_or(true, |v| v == parsed) {
 				*v = Some(parsed);
-				return Ok(commit.sha);
+				found = Some(parsed);
 			}
 
 			// Also check if we have reached the latest statically known version
 			if parsed <= *LATEST_MAPPED.get().unwrap(/*fine*/) {
 				// Make an exception for unbounded check, in this case, we have reached our goal
-				if maximum_pages_request_limit.is_some() {
-					break 'outer;
-				} else {
-					return Ok(commit.sha);
+				if maximum_pages_request_limit.is_none() && found.is_none() {
+					reached = Some(commit.sha);
 				}
+				break 'outer;
 			}
 		}
+		if found.is_some() {
+			break 'outer;
+		}
 
 		checks += 1;
 		if let Some(check_limit) = maximum_pages_request_limit {
@@ -293,6 +575,13 @@ pub async fn find_version_sha(
 			}
 		}
 	}
+	persist_commits(&state.vromf_cache.commit_index, unpersisted).await;
+	if let Some(sha) = reached {
+		return Ok(sha);
+	}
+	if let Some(sha) = found.and_then(|found| state.vromf_cache.commit_pages.get(&found)) {
+		return Ok(sha.clone());
+	}
 	if let Some(v) = *v {
 		if v > latest_known_version {
 			return Err((
@@ -313,8 +602,9 @@ pub async fn print_latest_version(State(state): State<Arc<AppState>>) -> String
 
 static CACHED_SHAS: &str = include_str!("../../assets/commits.txt");
 const EARLIEST_VERSION: Version = Version::new(2, 27, 2, 20);
+const DEFAULT_COMMIT_INDEX: &str = "discovered_commits.txt";
 static LATEST_MAPPED: OnceLock<Version> = OnceLock::new();
-fn cached_shas() -> DashMap<Version, String> {
+fn cached_shas(commit_index: &StdPath) -> DashMap<Version, String> {
 	let it: DashMap<Version, String> = CACHED_SHAS
 		.lines()
 		.map(|e| e.split(" "))
@@ -328,9 +618,320 @@ fn cached_shas() -> DashMap<Version, String> {
 		.filter(|&(v, _)| v >= EARLIEST_VERSION)
 		.collect();
 
+	// Commits discovered by previous runs, the embedded list takes precedence.
+	// Later lines replace earlier ones, as re-uploads of a version are appended with their older commit
+	let discovered = read_commit_index(commit_index)
+		.into_iter()
+		.collect::<HashMap<_, _>>();
+	for (version, sha) in discovered {
+		if version >= EARLIEST_VERSION {
+			it.entry(version).or_insert(sha);
+		}
+	}
+
 	{
 		let max = it.iter().max_by_key(|e| *e.key()).unwrap();
-		LATEST_MAPPED.set(*max.key()).unwrap();
+		// Only the first cache sets it, tests build several
+		let _ = LATEST_MAPPED.set(*max.key());
 	}
 	it
 }
+
+/// On-disk index of commits discovered at runtime, configurable through COMMIT_INDEX
+fn commit_index_path() -> PathBuf {
+	env::var("COMMIT_INDEX")
+		.unwrap_or_else(|_| DEFAULT_COMMIT_INDEX.to_owned())
+		.into()
+}
+
+/// Extracts the version from a datamine commit message such as "2.39.0.61" or "Update 2.39.0.61 (hotfix)".
+/// Merges and messages without exactly one version yield none
+pub fn parse_commit_version(message: &str) -> Option<Version> {
+	let title = message.lines().next()?.trim();
+	if title.starts_with("Merge ") {
+		return None;
+	}
+	let mut versions = title
+		.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+		.map(|token| token.trim_matches('.'))
+		.filter(|token| token.matches('.').count() == 3)
+		.filter_map(|token| Version::from_str(token).ok());
+	let version = versions.next()?;
+	versions.next().is_none().then_some(version)
+}
+
+/// Reads an index of `<sha> <version>` lines such as assets/commits.txt, skipping malformed ones
+pub fn read_commit_index(path: &StdPath) -> Vec<(Version, String)> {
+	let index = match fs::read_to_string(path) {
+		Ok(index) => index,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return vec![],
+		Err(e) => {
+			warn!(
+				"Failed to read commit index {}. Reason: {e}",
+				path.display()
+			);
+			return vec![];
+		},
+	};
+	index
+		.lines()
+		.filter(|line| !line.trim().is_empty())
+		.filter_map(|line| {
+			let parsed = line.split_once(' ').and_then(|(sha, version)| {
+				Some((Version::from_str(version.trim()).ok()?, sha.to_owned()))
+			});
+			if parsed.is_none() {
+				warn!("Skipping malformed line in {}: {line}", path.display());
+			}
+			parsed
+		})
+		.collect()
+}
+
+/// Appends newly discovered commits to the on-disk index, so that restarts do not crawl GitHub for them again
+async fn persist_commits(path: &StdPath, commits: Vec<(Version, String)>) {
+	if commits.is_empty() {
+		return;
+	}
+	let lines = commits
+		.iter()
+		.map(|(version, sha)| format!("{sha} {version}\n"))
+		.collect::<String>();
+	let res = spawn_blocking({
+		let path = path.to_owned();
+		move || {
+			OpenOptions::new()
+				.create(true)
+				.append(true)
+				.open(path)
+				.and_then(|mut index| index.write_all(lines.as_bytes()))
+		}
+	})
+	.await
+	.unwrap_or_else(|e| Err(io::Error::other(e)));
+	if let Err(e) = res {
+		warn!(
+			"Failed to persist {} commits to {}. Reason: {e}",
+			commits.len(),
+			path.display()
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use axum::{
+		extract::{Path, Query, State},
+		routing::get,
+		Json,
+		Router,
+	};
+	use octocrab::Octocrab;
+	use serde_json::{json, Value};
+	use tokio::net::TcpListener;
+
+	use super::*;
+
+	/// Answers the GitHub requests of a cache refresh, with one commit newer than the embedded list
+	struct MockGithub {
+		base:     String,
+		latest:   (Version, String),
+		newer:    (Version, String),
+		// Later commit of the newer version, which has to lose against the original upload
+		reupload: String,
+		// Vromf which differs between both commits
+		changed:  VromfType,
+	}
+
+	impl MockGithub {
+		fn vromf(&self, sha: &str, vromf: &str) -> Vec<u8> {
+			if sha == self.newer.1 && vromf == self.changed.as_ref() {
+				format!("changed {vromf}").into_bytes()
+			} else {
+				vromf.as_bytes().to_vec()
+			}
+		}
+	}
+
+	fn commit(sha: &str, message: String) -> Value {
+		json!({
+			"url": "https://example.com/", "sha": sha, "node_id": "", "html_url": "", "comments_url": "",
+			"commit": {
+				"url": "https://example.com/", "author": null, "committer": null, "message": message,
+				"comment_count": 0, "tree": { "sha": sha, "url": "https://example.com/" },
+			},
+			"author": null, "committer": null, "parents": [],
+		})
+	}
+
+	async fn commits(
+		State(mock): State<Arc<MockGithub>>,
+		Query(query): Query<HashMap<String, String>>,
+	) -> Json<Value> {
+		if query.get("page").map(String::as_str) != Some("1") {
+			return Json(json!([]));
+		}
+		let (newer, latest) = (&mock.newer, &mock.latest);
+		Json(json!([
+			commit(&mock.reupload, format!("{} (re-upload)", newer.0)),
+			commit(&newer.1, newer.0.to_string()),
+			commit(&latest.1, latest.0.to_string()),
+		]))
+	}
+
+	async fn contents(
+		State(mock): State<Arc<MockGithub>>,
+		Path((_, _, path)): Path<(String, String, String)>,
+		Query(query): Query<HashMap<String, String>>,
+	) -> Json<Value> {
+		let sha = &query["ref"];
+		let vromf = path.trim_start_matches("raw/");
+		let buf = mock.vromf(sha, vromf);
+		Json(json!({
+			"name": vromf, "path": path, "sha": git_blob_sha(&buf), "encoding": null, "content": null,
+			"size": buf.len(), "url": "https://example.com/", "html_url": null, "git_url": null,
+			"download_url": format!("{}/download/{sha}/{vromf}", mock.base), "type": "file",
+			"_links": { "git": null, "html": null, "self": "https://example.com/" }, "license": null,
+		}))
+	}
+
+	async fn download(
+		State(mock): State<Arc<MockGithub>>,
+		Path((sha, vromf)): Path<(String, String)>,
+	) -> Vec<u8> {
+		mock.vromf(&sha, &vromf)
+	}
+
+	#[tokio::test]
+	async fn refresh_publishes_newer_version() {
+		let dir = tempfile::tempdir().unwrap();
+		let index = dir.path().join("commits.txt");
+		let mut state = AppState::default();
+		state.vromf_cache = VromfCache::new(index.clone());
+
+		let latest = state.vromf_cache.latest_known_version();
+		let (head, build) = latest
+			.to_string()
+			.rsplit_once('.')
+			.map(|(h, b)| (h.to_owned(), b.to_owned()))
+			.unwrap();
+		let newer =
+			Version::from_str(&format!("{head}.{}", build.parse::<u32>().unwrap() + 1)).unwrap();
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let base = format!("http://{}", listener.local_addr().unwrap());
+		let mock = Arc::new(MockGithub {
+			base:     base.clone(),
+			latest:   (
+				latest,
+				state.vromf_cache.commit_pages.get(&latest).unwrap().clone(),
+			),
+			newer:    (newer, "f".repeat(40)),
+			reupload: "e".repeat(40),
+			changed:  VromfType::Aces,
+		});
+		let app = Router::new()
+			.route("/repos/:owner/:repo/commits", get(commits))
+			.route("/repos/:owner/:repo/contents/*path", get(contents))
+			.route("/download/:sha/:vromf", get(download))
+			.with_state(mock);
+		tokio::spawn(async move { axum::serve(listener, app).await });
+
+		state.octocrab = Octocrab::builder().base_uri(base).unwrap().build().unwrap();
+		let state = Arc::new(state);
+		let mut events = state.version_events.subscribe();
+		// The startup crawl maps the newer version before the refresh gets to it
+		find_version_sha(
+			state.clone(),
+			&mut Some(Version::new(u16::MAX, u16::MAX, u16::MAX, u16::MAX)),
+			Priority::Background,
+			None,
+		)
+		.await
+		.unwrap();
+		assert_eq!(state.vromf_cache.latest_known_version(), newer);
+		pull_vromf_to_cache(state.clone(), None, Priority::User)
+			.await
+			.unwrap();
+		let persisted = read_commit_index(&index);
+
+		let event = events.try_recv().expect("newer version to be published");
+		assert_eq!(event.version, newer.to_string());
+		assert_eq!(event.changed_vromfs, vec![VromfType::Aces.as_ref()]);
+		assert_eq!(state.vromf_cache.announced(), newer);
+		assert!(state.vromf_cache.elems.contains_key(&newer));
+		// Of both uploads the older one is kept, and persisted last so that it wins on restart
+		assert_eq!(event.sha, "f".repeat(40));
+		assert_eq!(
+			*state.vromf_cache.commit_pages.get(&newer).unwrap(),
+			"f".repeat(40)
+		);
+		assert_eq!(persisted.last(), Some(&(newer, "f".repeat(40))));
+	}
+
+	#[test]
+	fn git_blob_sha_matches_git() {
+		// `printf 'hello\n' | git hash-object --stdin`
+		assert_eq!(
+			git_blob_sha(b"hello\n"),
+			"ce013625030ba8dba906f756967f9e9ca394464a"
+		);
+		// `git hash-object /dev/null`
+		assert_eq!(
+			git_blob_sha(b""),
+			"e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"
+		);
+	}
+
+	fn version(v: &str) -> Option<Version> {
+		Some(Version::from_str(v).unwrap())
+	}
+
+	#[test]
+	fn parse_commit_versions() {
+		assert_eq!(parse_commit_version("2.39.0.61"), version("2.39.0.61"));
+		assert_eq!(
+			parse_commit_version("Update 2.39.0.61 (hotfix)"),
+			version("2.39.0.61")
+		);
+		assert_eq!(parse_commit_version("2.39.0.61."), version("2.39.0.61"));
+		assert_eq!(parse_commit_version("v2.39.0.61..."), version("2.39.0.61"));
+		assert_eq!(
+			parse_commit_version("2.39.0.61\n\nmentions 2.38.0.1 in the body"),
+			version("2.39.0.61")
+		);
+	}
+
+	#[test]
+	fn parse_commit_versions_rejects_ambiguous_messages() {
+		assert_eq!(
+			parse_commit_version("Merge pull request #1 from 2.39.0.61"),
+			None
+		);
+		assert_eq!(parse_commit_version("2.39.0.61 and 2.39.0.62"), None);
+		assert_eq!(parse_commit_version("Update readme"), None);
+		assert_eq!(parse_commit_version("2.39.0"), None);
+		assert_eq!(parse_commit_version(""), None);
+	}
+
+	#[test]
+	fn read_commit_index_skips_malformed_lines() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("commits.txt");
+		fs::write(
+			&path,
+			"aaaa 2.39.0.61\n\nnot-a-line\nbbbb not.a.version\ncccc 2.39.0.62 \naaaa 2.39.0.61\n",
+		)
+		.unwrap();
+		let index = read_commit_index(&path);
+
+		assert_eq!(
+			index,
+			vec![
+				(version("2.39.0.61").unwrap(), "aaaa".to_owned()),
+				(version("2.39.0.62").unwrap(), "cccc".to_owned()),
+				(version("2.39.0.61").unwrap(), "aaaa".to_owned()),
+			]
+		);
+		assert!(read_commit_index(&dir.path().join("missing.txt")).is_empty());
+	}
+}
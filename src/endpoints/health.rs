@@ -1,18 +1,26 @@
-use axum::Json;
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
 use serde::Serialize;
 use time::OffsetDateTime;
 
-use crate::error::ApiError;
+use crate::{app_state::AppState, error::ApiError, rate_limit::Quota};
 
 #[derive(Serialize, Clone, Debug)]
 pub struct HealthResponse {
-	time: String,
+	time:            String,
+	/// GitHub API quota as of the last response, none before the first one
+	github_quota:    Option<Quota>,
+	/// Seconds until GitHub is asked again, if it is throttling us
+	github_throttle: Option<i64>,
 }
 
-impl Default for HealthResponse {
-	fn default() -> Self {
+impl HealthResponse {
+	fn new(state: &AppState) -> Self {
 		Self {
-			time: time::OffsetDateTime::now_utc().to_string(),
+			time:            time::OffsetDateTime::now_utc().to_string(),
+			github_quota:    state.rate_limit.quota(),
+			github_throttle: state.rate_limit.retry_after(),
 		}
 	}
 }
@@ -21,9 +29,9 @@ impl Default for HealthResponse {
 	get,
 	path = "/health",
 	responses(
-        (status = 200, description = "UTC time of the server", content_type = ["text/json"]),
+        (status = 200, description = "UTC time of the server and the GitHub API quota as of the last response from GitHub", content_type = ["text/json"]),
 	)
 )]
-pub async fn health() -> ApiError<Json<HealthResponse>> {
-	Ok(Json(HealthResponse::default()))
+pub async fn health(State(state): State<Arc<AppState>>) -> ApiError<Json<HealthResponse>> {
+	Ok(Json(HealthResponse::new(&state)))
 }
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use axum::{
+	extract::{Path, Query, State},
+	Json,
+};
+use http::StatusCode;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::{
+	app_state::AppState,
+	error::ApiError,
+	lang_table::{LangTable, LocalizedKey},
+};
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct LangParams {
+	#[param(example = "English", default = "All languages")]
+	/// Only return the translation in this language
+	language: Option<String>,
+}
+
+#[utoipa::path(
+	get,
+	path = "/lang/{version}/{key}",
+	params(
+		("version" = String, description = "Either version string or literal \"latest\"", example = "latest"),
+		("key" = String, description = "Localization key", example = "f_16a_shop"),
+		LangParams
+	),
+	responses(
+        (status = 200, description = "Translations of the key", body = LocalizedKey, content_type = ["application/json"]),
+		(status = 400, description = "Version or language invalid"),
+		(status = 404, description = "Key does not exist"),
+	)
+)]
+pub async fn get_lang_key(
+	State(state): State<Arc<AppState>>,
+	Path((version, key)): Path<(String, String)>,
+	Query(params): Query<LangParams>,
+) -> ApiError<Json<LocalizedKey>> {
+	let version = state.vromf_cache.resolve_version(Some(&version))?;
+	let table = LangTable::get(state.clone(), version).await?;
+	let language = params
+		.language
+		.as_deref()
+		.map(|l| table.language(l))
+		.transpose()?;
+
+	table.lookup(&key, language).map(Json).ok_or_else(|| {
+		(
+			StatusCode::NOT_FOUND,
+			format!("Localization key {key} not found"),
+		)
+	})
+}
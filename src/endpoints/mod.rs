@@ -1,4 +1,13 @@
+pub mod events;
+pub mod export;
+pub mod feed;
 pub mod files;
 pub mod get_vromfs;
 pub mod health;
+pub mod lang;
+pub mod query;
+pub mod search;
+pub mod units;
 pub mod versions;
+pub mod weapons;
+pub mod webhooks;
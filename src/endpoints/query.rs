@@ -0,0 +1,376 @@
+use std::{
+	env,
+	io::{self, BufRead, Write},
+	path::Path as StdPath,
+	process::Stdio,
+	str::FromStr,
+	sync::Arc,
+	time::Duration,
+};
+
+use axum::{
+	extract::{Query, State},
+	Json,
+};
+use http::StatusCode;
+use jaq_core::{
+	load::{Arena, File, Loader},
+	Compiler,
+	Ctx,
+	Filter,
+	Native,
+	RcIter,
+};
+use jaq_json::Val;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+	io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+	process::{Child, ChildStdin, Command},
+};
+use utoipa::{IntoParams, ToSchema};
+use wt_blk::vromf::BlkOutputFormat;
+use wt_version::Version;
+
+use crate::{
+	app_state::AppState,
+	endpoints::{files::UnpackedVromfs, search::PathPattern},
+	error::ApiError,
+	eyre_error_translation::{EyreToApiError, OptionToApiError},
+	vromf_enum::VromfType,
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_TIMEOUT: Duration = Duration::from_secs(30);
+/// Total size of all returned values when serialized as JSON
+const MAX_OUTPUT_SIZE: usize = 16 * 1024 * 1024;
+/// Heap and stack of the worker process running the filter
+const MAX_WORKER_MEMORY: u64 = 1024 * 1024 * 1024;
+/// Worker processes running at once, further queries wait for a permit within their time limit
+pub const MAX_WORKERS: usize = 4;
+/// Argument starting the binary as query worker instead of the server
+pub const WORKER_ARG: &str = "query-worker";
+/// Worker exit code once MAX_OUTPUT_SIZE is exceeded
+const EXIT_TOO_LARGE: i32 = 3;
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct QueryParams {
+	#[param(example = ".rocket.maxDistance")]
+	/// jq filter applied to every selected BLK
+	filter:     String,
+	#[param(example = "aces.vromfs.bin/gamedata/weapons/rocketguns/*.blk")]
+	/// Path of a single BLK, or a glob selecting many, starting with the vromf
+	path:       String,
+	#[param(example = "latest", default = "Latest available")]
+	/// Either version string or literal "latest"
+	version:    Option<String>,
+	#[param(example = 5000, default = 10000)]
+	/// Time limit of the query in milliseconds, capped at 30 seconds
+	timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct QueryResponse {
+	/// Outputs of the filter per file, files without output are left out
+	results: Vec<QueryResult>,
+	/// Files the filter failed on
+	errors:  Vec<QueryError>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QueryResult {
+	path:   String,
+	values: Vec<Value>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QueryError {
+	path:  String,
+	error: String,
+}
+
+/// Outputs of the filter on one input, as sent back by the worker process
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct WorkerOutput {
+	values: Vec<Value>,
+	/// Error ending the filter run, values before it are kept
+	error:  Option<String>,
+}
+
+#[utoipa::path(
+	get,
+	path = "/query",
+	params(QueryParams),
+	responses(
+        (status = 200, description = "Filter outputs per matched BLK", body = QueryResponse, content_type = ["application/json"]),
+		(status = 400, description = "Filter, path or version invalid"),
+		(status = 404, description = "No BLK matches the path"),
+		(status = 408, description = "Query exceeded its time limit"),
+		(status = 413, description = "Query output or memory use exceeded its limit"),
+	)
+)]
+pub async fn query(
+	State(state): State<Arc<AppState>>,
+	Query(params): Query<QueryParams>,
+) -> ApiError<Json<QueryResponse>> {
+	let version = state
+		.vromf_cache
+		.resolve_version(params.version.as_deref())?;
+	let timeout = params
+		.timeout_ms
+		.map(Duration::from_millis)
+		.unwrap_or(DEFAULT_TIMEOUT)
+		.min(MAX_TIMEOUT);
+
+	let (vromf, pattern) = params.path.split_once('/').unwrap_or((&params.path, ""));
+	let vromf = VromfType::from_str(vromf).map_err(|_| {
+		(
+			StatusCode::NOT_FOUND,
+			format!("Vromf doesnt exist: {vromf}"),
+		)
+	})?;
+	let pattern = PathPattern::new(pattern, None)?;
+	let paths = UnpackedVromfs::file_index(state.clone(), version, vromf)
+		.await?
+		.iter()
+		.filter(|e| e.path.ends_with(".blk") && pattern.is_match(&e.path))
+		.map(|e| e.path.clone())
+		.collect::<Vec<_>>();
+	if paths.is_empty() {
+		return Err((
+			StatusCode::NOT_FOUND,
+			format!("No BLK matches {}", params.path),
+		));
+	}
+
+	// Filters may loop, recurse or allocate without bound, so they run in a separate process
+	// that is killed on timeout and cannot take the server down with it
+	compile(&params.filter).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+	let res = tokio::time::timeout(timeout, async {
+		let _permit = state.query_workers.acquire().await.convert_err()?;
+		run_in_worker(state.clone(), version, vromf, paths, &params.filter).await
+	})
+	.await
+	.map_err(|_| timed_out(timeout))??;
+	Ok(Json(res))
+}
+
+async fn run_in_worker(
+	state: Arc<AppState>,
+	version: Version,
+	vromf: VromfType,
+	paths: Vec<String>,
+	filter: &str,
+) -> ApiError<QueryResponse> {
+	let mut worker = Command::new(env::current_exe().convert_err()?)
+		.arg(WORKER_ARG)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		// Dropping the future on timeout or client disconnect kills the worker
+		.kill_on_drop(true)
+		.spawn()
+		.convert_err()?;
+	let mut stdin = worker
+		.stdin
+		.take()
+		.convert_err("worker stdin to be piped")?;
+	let mut stdout = BufReader::new(
+		worker
+			.stdout
+			.take()
+			.convert_err("worker stdout to be piped")?,
+	)
+	.lines();
+
+	if write_line(&mut stdin, serde_json::to_vec(filter).convert_err()?)
+		.await
+		.is_err()
+	{
+		return Err(worker_failed(&mut worker).await);
+	}
+
+	let mut res = QueryResponse::default();
+	for path in paths {
+		let input = match unpack_json(state.clone(), version, vromf, path.clone()).await? {
+			Ok(input) => input,
+			Err(error) => {
+				res.errors.push(QueryError { path, error });
+				continue;
+			},
+		};
+		if write_line(&mut stdin, input).await.is_err() {
+			return Err(worker_failed(&mut worker).await);
+		}
+		let Some(output) = stdout.next_line().await.convert_err()? else {
+			return Err(worker_failed(&mut worker).await);
+		};
+		let output = serde_json::from_str::<WorkerOutput>(&output).convert_err()?;
+		if let Some(error) = output.error {
+			res.errors.push(QueryError {
+				path: path.clone(),
+				error,
+			});
+		}
+		if !output.values.is_empty() {
+			res.results.push(QueryResult {
+				path,
+				values: output.values,
+			});
+		}
+	}
+	Ok(res)
+}
+
+/// Unpacks a BLK on the worker pool as single line JSON, or the reason it failed
+async fn unpack_json(
+	state: Arc<AppState>,
+	version: Version,
+	vromf: VromfType,
+	path: String,
+) -> ApiError<Result<Vec<u8>, String>> {
+	state
+		.clone()
+		.spawn_worker(move |s| {
+			let res = || {
+				let unpacker = state.unpacked_vromfs.unpacker(version, vromf)?;
+				let (_, buf) = unpacker
+					.unpack_one(StdPath::new(&path), Some(BlkOutputFormat::Json), true)
+					.convert_err()?
+					.split();
+				let input = serde_json::from_slice::<Value>(&buf).convert_err()?;
+				serde_json::to_vec(&input).convert_err()
+			};
+			let _ = s.send(res().map_err(|(_, e)| e));
+		})
+		.await
+}
+
+async fn write_line(stdin: &mut ChildStdin, mut line: Vec<u8>) -> io::Result<()> {
+	line.push(b'\n');
+	stdin.write_all(&line).await?;
+	stdin.flush().await
+}
+
+/// Reports why the worker stopped responding
+async fn worker_failed(worker: &mut Child) -> (StatusCode, String) {
+	match worker.wait().await {
+		Ok(status) if status.code() == Some(EXIT_TOO_LARGE) => too_large(),
+		// Killed by a signal, which is how exhausting memory or the stack ends
+		Ok(status) if status.code().is_none() => (
+			StatusCode::PAYLOAD_TOO_LARGE,
+			format!(
+				"Query exceeded its memory limit of {MAX_WORKER_MEMORY} bytes or recursed too deep"
+			),
+		),
+		Ok(status) => (
+			StatusCode::INTERNAL_SERVER_ERROR,
+			format!("Query worker exited with {status}"),
+		),
+		Err(e) => (
+			StatusCode::INTERNAL_SERVER_ERROR,
+			format!("Query worker could not be awaited: {e}"),
+		),
+	}
+}
+
+/// Runs filters in the process started with WORKER_ARG, returning its exit code.
+/// Reads the filter and then one input per line from stdin, answering each input with a line of WorkerOutput.
+pub fn run_worker() -> i32 {
+	#[cfg(unix)]
+	{
+		let limit = libc::rlimit {
+			rlim_cur: MAX_WORKER_MEMORY,
+			rlim_max: MAX_WORKER_MEMORY,
+		};
+		// SAFETY: setrlimit only reads the passed limit
+		if unsafe { libc::setrlimit(libc::RLIMIT_DATA, &limit) } != 0 {
+			eprintln!(
+				"Failed to limit query worker memory: {}",
+				io::Error::last_os_error()
+			);
+			return 1;
+		}
+	}
+
+	let mut lines = io::stdin().lock().lines();
+	let mut stdout = io::stdout().lock();
+	let Some(filter) = lines
+		.next()
+		.and_then(|line| serde_json::from_str::<String>(&line.ok()?).ok())
+		.and_then(|filter| compile(&filter).ok())
+	else {
+		return 1;
+	};
+
+	let mut output_size = 0;
+	for line in lines {
+		let Some(input) = line
+			.ok()
+			.and_then(|line| serde_json::from_str::<Value>(&line).ok())
+		else {
+			return 1;
+		};
+		let inputs = RcIter::new(core::iter::empty());
+		let mut output = WorkerOutput::default();
+		for value in filter.run((Ctx::new([], &inputs), Val::from(input))) {
+			match value {
+				Ok(value) => {
+					let value = Value::from(value);
+					output_size += serde_json::to_vec(&value).map_or(0, |v| v.len());
+					if output_size > MAX_OUTPUT_SIZE {
+						return EXIT_TOO_LARGE;
+					}
+					output.values.push(value);
+				},
+				Err(e) => {
+					output.error = Some(e.to_string());
+					break;
+				},
+			}
+		}
+		let written = serde_json::to_writer(&mut stdout, &output)
+			.map_err(io::Error::from)
+			.and_then(|_| writeln!(stdout))
+			.and_then(|_| stdout.flush());
+		if written.is_err() {
+			return 1;
+		}
+	}
+	0
+}
+
+fn too_large() -> (StatusCode, String) {
+	(
+		StatusCode::PAYLOAD_TOO_LARGE,
+		format!("Query output exceeded the limit of {MAX_OUTPUT_SIZE} bytes"),
+	)
+}
+
+fn timed_out(timeout: Duration) -> (StatusCode, String) {
+	(
+		StatusCode::REQUEST_TIMEOUT,
+		format!("Query exceeded its time limit of {timeout:?}"),
+	)
+}
+
+fn compile(code: &str) -> Result<Filter<Native<Val>>, String> {
+	let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+	let arena = Arena::default();
+	let modules = loader
+		.load(&arena, File { code, path: () })
+		.map_err(|errs| {
+			let errs = errs.into_iter().map(|(_, e)| e).collect::<Vec<_>>();
+			format!("invalid filter: {errs:?}")
+		})?;
+	Compiler::default()
+		.with_funs(jaq_std::funs().chain(jaq_json::funs()))
+		.compile(modules)
+		.map_err(|errs| {
+			let undefined = errs
+				.into_iter()
+				.flat_map(|(_, e)| e)
+				.map(|(name, _)| name)
+				.collect::<Vec<_>>();
+			format!("undefined in filter: {}", undefined.join(", "))
+		})
+}
@@ -0,0 +1,246 @@
+use std::{str::FromStr, sync::Arc};
+
+use axum::{
+	extract::{Query, State},
+	Json,
+};
+use globset::{GlobBuilder, GlobMatcher};
+use http::StatusCode;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use strum::VariantArray;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+	app_state::AppState,
+	content_index::{ContentIndex, ContentMatch},
+	endpoints::files::UnpackedVromfs,
+	error::ApiError,
+	lang_table::{LangTable, LocalizedKey},
+	vromf_enum::VromfType,
+};
+
+const DEFAULT_MATCH_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct PathSearchParams {
+	#[param(example = "gamedata/units/tankmodels/*_t_*.blk")]
+	/// Pattern matched against file paths within the vromf
+	pattern: String,
+	#[param(example = "glob", default = "glob")]
+	/// How to interpret the pattern. One of: [glob, regex]
+	mode:    Option<String>,
+	#[param(example = "latest", default = "Latest available")]
+	/// Either version string or literal "latest"
+	version: Option<String>,
+	#[param(example = "aces.vromfs.bin", default = "All vromfs")]
+	/// Restricts the search to a single vromf
+	vromf:   Option<String>,
+	#[param(example = 100, default = 1000)]
+	/// Maximum amount of matches returned
+	limit:   Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PathMatch {
+	vromf: &'static str,
+	path:  String,
+	size:  usize,
+}
+
+pub enum PathPattern {
+	Glob(GlobMatcher),
+	Regex(Regex),
+}
+
+impl PathPattern {
+	pub fn new(pattern: &str, mode: Option<&str>) -> ApiError<Self> {
+		let invalid = |e: String| {
+			(
+				StatusCode::BAD_REQUEST,
+				format!("invalid pattern {pattern}: {e}"),
+			)
+		};
+		match mode.map(str::to_ascii_lowercase).as_deref() {
+			None | Some("glob") => Ok(Self::Glob(
+				GlobBuilder::new(pattern)
+					.literal_separator(true)
+					.build()
+					.map_err(|e| invalid(e.to_string()))?
+					.compile_matcher(),
+			)),
+			Some("regex") => Ok(Self::Regex(
+				Regex::new(pattern).map_err(|e| invalid(e.to_string()))?,
+			)),
+			Some(mode) => Err((
+				StatusCode::BAD_REQUEST,
+				format!("unknown pattern mode: {mode}"),
+			)),
+		}
+	}
+
+	pub fn is_match(&self, path: &str) -> bool {
+		match self {
+			PathPattern::Glob(g) => g.is_match(path),
+			PathPattern::Regex(r) => r.is_match(path),
+		}
+	}
+}
+
+/// Parses an optional vromf name, where none selects every vromf
+pub fn select_vromfs(vromf: Option<&str>) -> ApiError<Vec<VromfType>> {
+	match vromf {
+		None => Ok(VromfType::VARIANTS.to_vec()),
+		Some(v) => Ok(vec![VromfType::from_str(v).map_err(|_| {
+			(StatusCode::NOT_FOUND, format!("Vromf doesnt exist: {v}"))
+		})?]),
+	}
+}
+
+#[utoipa::path(
+	get,
+	path = "/search/paths",
+	params(PathSearchParams),
+	responses(
+        (status = 200, description = "Files matching the pattern with their raw sizes", body = [PathMatch], content_type = ["application/json"]),
+		(status = 400, description = "Pattern, mode or version invalid"),
+		(status = 404, description = "Provided vromf does not exist"),
+	)
+)]
+pub async fn search_paths(
+	State(state): State<Arc<AppState>>,
+	Query(params): Query<PathSearchParams>,
+) -> ApiError<Json<Vec<PathMatch>>> {
+	let version = state
+		.vromf_cache
+		.resolve_version(params.version.as_deref())?;
+	let pattern = PathPattern::new(&params.pattern, params.mode.as_deref())?;
+	let limit = params.limit.unwrap_or(DEFAULT_MATCH_LIMIT);
+
+	let mut matches = vec![];
+	for vromf in select_vromfs(params.vromf.as_deref())? {
+		let index = UnpackedVromfs::file_index(state.clone(), version, vromf).await?;
+		let remaining = limit - matches.len();
+		matches.extend(
+			index
+				.iter()
+				.filter(|e| pattern.is_match(&e.path))
+				.take(remaining)
+				.map(|e| PathMatch {
+					vromf: vromf.into(),
+					path:  e.path.clone(),
+					size:  e.size,
+				}),
+		);
+		if matches.len() >= limit {
+			break;
+		}
+	}
+	Ok(Json(matches))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ContentSearchParams {
+	#[param(example = "mica_em")]
+	/// Case-insensitive text to look for in BLK string values and text file lines
+	q:       String,
+	#[param(example = "latest", default = "Latest available")]
+	/// Either version string or literal "latest"
+	version: Option<String>,
+	#[param(example = 100, default = 1000)]
+	/// Maximum amount of matches returned
+	limit:   Option<usize>,
+}
+
+#[utoipa::path(
+	get,
+	path = "/search/content",
+	params(ContentSearchParams),
+	responses(
+        (status = 200, description = "Matching values with their file, key path or line and a snippet", body = [ContentMatch], content_type = ["application/json"]),
+		(status = 400, description = "Query or version invalid"),
+		(status = 503, description = "Index for this version is still being built, retry later"),
+	)
+)]
+pub async fn search_content(
+	State(state): State<Arc<AppState>>,
+	Query(params): Query<ContentSearchParams>,
+) -> ApiError<Json<Vec<ContentMatch>>> {
+	let version = state
+		.vromf_cache
+		.resolve_version(params.version.as_deref())?;
+	if !params.q.chars().any(char::is_alphanumeric) {
+		return Err((
+			StatusCode::BAD_REQUEST,
+			"query has to contain at least one word".to_owned(),
+		));
+	}
+
+	let index = state
+		.content_indices
+		.get(&version)
+		.map(|e| e.value().clone());
+	match index {
+		Some(Some(index)) => Ok(Json(
+			index.search(&params.q, params.limit.unwrap_or(DEFAULT_MATCH_LIMIT)),
+		)),
+		Some(None) => Err((
+			StatusCode::SERVICE_UNAVAILABLE,
+			format!("Content index for {version} is still being built"),
+		)),
+		None => {
+			// Not indexed yet, the version is downloaded and indexed in the background
+			ContentIndex::schedule_build(state.clone(), version);
+			Err((
+				StatusCode::SERVICE_UNAVAILABLE,
+				format!("Content index for {version} is still being built"),
+			))
+		},
+	}
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct LangSearchParams {
+	#[param(example = "F-16")]
+	/// Case-insensitive text to look for in localization keys and translations
+	q:        String,
+	#[param(example = "latest", default = "Latest available")]
+	/// Either version string or literal "latest"
+	version:  Option<String>,
+	#[param(example = "English", default = "All languages")]
+	/// Only search and return translations in this language
+	language: Option<String>,
+	#[param(example = 100, default = 1000)]
+	/// Maximum amount of matches returned
+	limit:    Option<usize>,
+}
+
+#[utoipa::path(
+	get,
+	path = "/search/lang",
+	params(LangSearchParams),
+	responses(
+        (status = 200, description = "Localization keys matching the query, sorted by key", body = [LocalizedKey], content_type = ["application/json"]),
+		(status = 400, description = "Version or language invalid"),
+	)
+)]
+pub async fn search_lang(
+	State(state): State<Arc<AppState>>,
+	Query(params): Query<LangSearchParams>,
+) -> ApiError<Json<Vec<LocalizedKey>>> {
+	let version = state
+		.vromf_cache
+		.resolve_version(params.version.as_deref())?;
+	let table = LangTable::get(state.clone(), version).await?;
+	let language = params
+		.language
+		.as_deref()
+		.map(|l| table.language(l))
+		.transpose()?;
+
+	Ok(Json(table.search(
+		&params.q,
+		language,
+		params.limit.unwrap_or(DEFAULT_MATCH_LIMIT),
+	)))
+}
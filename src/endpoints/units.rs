@@ -0,0 +1,255 @@
+use std::{str::FromStr, sync::Arc};
+
+use axum::{
+	extract::{Path, Query, State},
+	Json,
+};
+use futures::{stream, StreamExt, TryStreamExt};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+	app_state::AppState,
+	endpoints::files::UnpackedVromfs,
+	error::ApiError,
+	lang_table::LangTable,
+	unit_catalog::{BattleRatings, GameMode, RepairCosts, Unit, UnitCatalog, UnitType},
+	vromf_enum::VromfType,
+};
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct UnitParams {
+	#[param(example = "latest", default = "Latest available")]
+	/// Either version string or literal "latest"
+	version:   Option<String>,
+	#[param(example = "English", default = "English")]
+	/// Language of the unit names
+	language:  Option<String>,
+	#[param(example = "usa")]
+	/// Country, with or without the `country_` prefix
+	country:   Option<String>,
+	#[param(example = "aircraft")]
+	/// One of: [aircraft, helicopter, ground, fleet, other]
+	unit_type: Option<String>,
+	#[param(example = "exp_fighter")]
+	/// Unit class as named in wpcost.blk
+	class:     Option<String>,
+	#[param(example = 7)]
+	rank:      Option<u64>,
+	#[param(example = "realistic", default = "realistic")]
+	/// Mode min_br and max_br refer to. One of: [arcade, realistic, simulator]
+	mode:      Option<String>,
+	#[param(example = 10.0)]
+	min_br:    Option<f64>,
+	#[param(example = 11.3)]
+	max_br:    Option<f64>,
+	#[param(example = "F-16")]
+	/// Case-insensitive substring of the unit ID or its localized name
+	name:      Option<String>,
+}
+
+#[utoipa::path(
+	get,
+	path = "/units",
+	params(UnitParams),
+	responses(
+        (status = 200, description = "Units matching all filters, sorted by ID", body = [Unit], content_type = ["application/json"]),
+		(status = 400, description = "Version, language, unit type or mode invalid"),
+	)
+)]
+pub async fn list_units(
+	State(state): State<Arc<AppState>>,
+	Query(params): Query<UnitParams>,
+) -> ApiError<Json<Vec<Unit>>> {
+	let version = state
+		.vromf_cache
+		.resolve_version(params.version.as_deref())?;
+	let unit_type = params
+		.unit_type
+		.as_deref()
+		.map(|t| {
+			UnitType::from_str(t)
+				.map_err(|_| (StatusCode::BAD_REQUEST, format!("Unknown unit type: {t}")))
+		})
+		.transpose()?;
+	let mode = params
+		.mode
+		.as_deref()
+		.map(|m| {
+			GameMode::from_str(m)
+				.map_err(|_| (StatusCode::BAD_REQUEST, format!("Unknown game mode: {m}")))
+		})
+		.transpose()?
+		.unwrap_or_default();
+	let country = params
+		.country
+		.as_deref()
+		.map(|c| c.trim_start_matches("country_"));
+	let name = params.name.as_deref().map(str::to_lowercase);
+
+	let catalog = UnitCatalog::get(state.clone(), version).await?;
+	let aces_index = UnpackedVromfs::file_index(state.clone(), version, VromfType::Aces).await?;
+	let lang = LangTable::get(state.clone(), version).await?;
+	let language = lang.language(params.language.as_deref().unwrap_or("English"))?;
+
+	let units = catalog
+		.units
+		.iter()
+		.filter(|u| country.map_or(true, |c| u.country.eq_ignore_ascii_case(c)))
+		.filter(|u| unit_type.map_or(true, |t| u.unit_type == t))
+		.filter(|u| {
+			params
+				.class
+				.as_deref()
+				.map_or(true, |c| u.unit_class.eq_ignore_ascii_case(c))
+		})
+		.filter(|u| params.rank.map_or(true, |r| u.rank == r))
+		.filter(|u| {
+			params
+				.min_br
+				.map_or(true, |br| u.battle_ratings.get(mode) >= br)
+		})
+		.filter(|u| {
+			params
+				.max_br
+				.map_or(true, |br| u.battle_ratings.get(mode) <= br)
+		})
+		.map(|u| Unit {
+			blk_path: u.find_blk(&aces_index).map(ToOwned::to_owned),
+			..u.localized(&lang, language)
+		})
+		.filter(|u| {
+			name.as_deref().map_or(true, |n| {
+				u.id.to_lowercase().contains(n)
+					|| u.name
+						.as_deref()
+						.is_some_and(|l| l.to_lowercase().contains(n))
+			})
+		})
+		.collect();
+	Ok(Json(units))
+}
+
+/// Versions compared when the range has no start
+const DEFAULT_HISTORY_VERSIONS: usize = 10;
+/// Each version in the range needs its char.vromfs.bin downloaded and parsed, so ranges are kept short
+const MAX_HISTORY_VERSIONS: usize = 50;
+/// Versions downloaded and parsed at the same time
+const HISTORY_PARALLELISM: usize = 4;
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct HistoryParams {
+	#[param(example = "2.35.0.1", default = "10 versions before `to`")]
+	/// First version of the range, inclusive
+	from: Option<String>,
+	#[param(example = "latest", default = "Latest available")]
+	/// Last version of the range, inclusive. Either version string or literal "latest"
+	to:   Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UnitHistory {
+	id:     String,
+	/// One entry per known version in the range, oldest first
+	points: Vec<HistoryPoint>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HistoryPoint {
+	#[schema(example = "2.35.0.1")]
+	version: String,
+	/// Whether the stats differ from the previous version in the range
+	changed: bool,
+	/// Null when the unit does not exist in this version
+	stats:   Option<UnitStats>,
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct UnitStats {
+	rank:           u64,
+	battle_ratings: BattleRatings,
+	research_cost:  u64,
+	purchase_cost:  u64,
+	repair_costs:   RepairCosts,
+	weapon_presets: Vec<String>,
+}
+
+#[utoipa::path(
+	get,
+	path = "/units/{id}/history",
+	params(
+		("id" = String, description = "Unit ID as named in wpcost.blk", example = "f_16a"),
+		HistoryParams
+	),
+	responses(
+        (status = 200, description = "Stats of the unit per version", body = UnitHistory, content_type = ["application/json"]),
+		(status = 400, description = "Version range invalid or too long"),
+		(status = 404, description = "Unit does not exist in any version of the range"),
+	)
+)]
+pub async fn unit_history(
+	State(state): State<Arc<AppState>>,
+	Path(id): Path<String>,
+	Query(params): Query<HistoryParams>,
+) -> ApiError<Json<UnitHistory>> {
+	let to = state.vromf_cache.resolve_version(params.to.as_deref())?;
+	let from = params
+		.from
+		.as_deref()
+		.map(|v| state.vromf_cache.resolve_version(Some(v)))
+		.transpose()?;
+
+	let mut versions = state
+		.vromf_cache
+		.list_versions()
+		.map(|e| *e.key())
+		.filter(|v| *v <= to && from.map_or(true, |from| *v >= from))
+		.collect::<Vec<_>>();
+	versions.sort_unstable();
+	if from.is_some() && versions.len() > MAX_HISTORY_VERSIONS {
+		return Err((
+			StatusCode::BAD_REQUEST,
+			format!(
+				"Range spans {} versions, at most {MAX_HISTORY_VERSIONS} can be compared at once",
+				versions.len()
+			),
+		));
+	}
+	let window = if from.is_some() {
+		MAX_HISTORY_VERSIONS
+	} else {
+		DEFAULT_HISTORY_VERSIONS
+	};
+	let versions = &versions[versions.len().saturating_sub(window)..];
+
+	let catalogs = stream::iter(versions)
+		.map(|version| UnitCatalog::get(state.clone(), *version))
+		.buffered(HISTORY_PARALLELISM)
+		.try_collect::<Vec<_>>()
+		.await?;
+	let mut points = Vec::<HistoryPoint>::with_capacity(versions.len());
+	for (version, catalog) in versions.iter().zip(catalogs) {
+		let stats = catalog.unit(&id).map(|u| UnitStats {
+			rank:           u.rank,
+			battle_ratings: u.battle_ratings,
+			research_cost:  u.research_cost,
+			purchase_cost:  u.purchase_cost,
+			repair_costs:   u.repair_costs,
+			weapon_presets: u.weapon_presets.clone(),
+		});
+		points.push(HistoryPoint {
+			version: version.to_string(),
+			changed: points.last().is_some_and(|p| p.stats != stats),
+			stats,
+		});
+	}
+
+	if points.iter().all(|p| p.stats.is_none()) {
+		return Err((
+			StatusCode::NOT_FOUND,
+			format!("Unit {id} does not exist in the requested versions"),
+		));
+	}
+	Ok(Json(UnitHistory { id, points }))
+}
@@ -0,0 +1,84 @@
+use std::{str::FromStr, sync::Arc};
+
+use axum::{
+	extract::{Query, State},
+	Json,
+};
+use http::StatusCode;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::{
+	app_state::AppState,
+	error::ApiError,
+	lang_table::LangTable,
+	weapon_catalog::{Weapon, WeaponCatalog, WeaponKind},
+};
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct WeaponParams {
+	#[param(example = "latest", default = "Latest available")]
+	/// Either version string or literal "latest"
+	version:  Option<String>,
+	#[param(example = "English", default = "English")]
+	/// Language of the weapon names
+	language: Option<String>,
+	#[param(example = "rocket")]
+	/// One of: [rocket, bomb, torpedo, bullet, other]
+	kind:     Option<String>,
+	#[param(example = "rocketguns")]
+	/// Folder within gamedata/weapons
+	category: Option<String>,
+	#[param(example = "aim9")]
+	/// Case-insensitive substring of the weapon ID or its localized name
+	name:     Option<String>,
+}
+
+#[utoipa::path(
+	get,
+	path = "/weapons",
+	params(WeaponParams),
+	responses(
+        (status = 200, description = "Weapons matching all filters, sorted by path", body = [Weapon], content_type = ["application/json"]),
+		(status = 400, description = "Version, language or kind invalid"),
+	)
+)]
+pub async fn list_weapons(
+	State(state): State<Arc<AppState>>,
+	Query(params): Query<WeaponParams>,
+) -> ApiError<Json<Vec<Weapon>>> {
+	let version = state
+		.vromf_cache
+		.resolve_version(params.version.as_deref())?;
+	let kind = params
+		.kind
+		.as_deref()
+		.map(|k| {
+			WeaponKind::from_str(k)
+				.map_err(|_| (StatusCode::BAD_REQUEST, format!("Unknown weapon kind: {k}")))
+		})
+		.transpose()?;
+	let category = params.category.as_deref().map(|c| c.trim_matches('/'));
+	let name = params.name.as_deref().map(str::to_lowercase);
+
+	let catalog = WeaponCatalog::get(state.clone(), version).await?;
+	let lang = LangTable::get(state.clone(), version).await?;
+	let language = lang.language(params.language.as_deref().unwrap_or("English"))?;
+
+	let weapons = catalog
+		.weapons
+		.iter()
+		.filter(|w| kind.map_or(true, |k| w.kind == k))
+		.filter(|w| category.map_or(true, |c| w.category.eq_ignore_ascii_case(c)))
+		.map(|w| w.localized(&lang, language))
+		.filter(|w| {
+			name.as_deref().map_or(true, |n| {
+				w.id.to_lowercase().contains(n)
+					|| w.name
+						.as_deref()
+						.is_some_and(|l| l.to_lowercase().contains(n))
+			})
+		})
+		.collect();
+	Ok(Json(weapons))
+}
@@ -0,0 +1,16 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+
+use crate::{app_state::AppState, webhooks::Delivery};
+
+#[utoipa::path(
+	get,
+	path = "/webhooks/deliveries",
+	responses(
+        (status = 200, description = "Recent webhook deliveries, most recent first", body = [Delivery]),
+	)
+)]
+pub async fn webhook_deliveries(State(state): State<Arc<AppState>>) -> Json<Vec<Delivery>> {
+	Json(state.webhooks.deliveries())
+}
@@ -0,0 +1,46 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+use wt_version::Version;
+
+use crate::{app_state::AppState, vromf_enum::VromfType};
+
+/// Events which were not received by a subscriber within this many newer events are dropped for it
+pub const EVENT_CAPACITY: usize = 16;
+
+/// Published whenever the cache refresh discovers a newer game version
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct VersionEvent {
+	#[schema(example = "2.41.0.25")]
+	pub version:        String,
+	/// Commit of the datamine repository the version was found in
+	pub sha:            String,
+	/// Vromfs which differ from the previously latest version, all of them if they could not be compared
+	#[schema(example = json!(["aces.vromfs.bin", "lang.vromfs.bin"]))]
+	pub changed_vromfs: Vec<&'static str>,
+	/// Unix timestamp of the discovery
+	pub discovered_at:  i64,
+}
+
+impl VersionEvent {
+	pub fn new(version: Version, sha: String, changed_vromfs: Vec<VromfType>) -> Self {
+		Self {
+			version: version.to_string(),
+			sha,
+			changed_vromfs: changed_vromfs.into_iter().map(Into::into).collect(),
+			discovered_at: OffsetDateTime::now_utc().unix_timestamp(),
+		}
+	}
+}
+
+pub fn channel() -> broadcast::Sender<Arc<VersionEvent>> {
+	broadcast::channel(EVENT_CAPACITY).0
+}
+
+/// Notifies every subscriber, having none is not an error
+pub fn publish(state: &AppState, event: VersionEvent) {
+	let _ = state.version_events.send(Arc::new(event));
+}
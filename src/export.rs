@@ -0,0 +1,555 @@
+use std::{
+	env,
+	fs,
+	future::Future,
+	io::{self, Write},
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use arrow_array::{
+	builder::{ListBuilder, StringBuilder},
+	ArrayRef,
+	Float64Array,
+	RecordBatch,
+	StringArray,
+	UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::{
+	arrow::ArrowWriter,
+	basic::{Compression, ZstdLevel},
+	file::properties::WriterProperties,
+};
+use rusqlite::{params, types::Value as SqlValue, Connection};
+use serde_json::Value;
+use strum::VariantArray;
+use tokio::{
+	sync::{Mutex, OwnedMutexGuard},
+	task::spawn_blocking,
+};
+use tracing::{info, warn};
+use wt_blk::vromf::BlkOutputFormat;
+use wt_version::Version;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+	app_state::AppState,
+	endpoints::files::UnpackedVromfs,
+	error::ApiError,
+	eyre_error_translation::EyreToApiError,
+	flatten::{leaf_to_string, visit_leaves},
+	lang_table::LangTable,
+	output_format::is_blk,
+	unit_catalog::{Unit, UnitCatalog},
+	vromf_enum::VromfType,
+	weapon_catalog::{Weapon, WeaponCatalog},
+};
+
+/// Exports are expensive to generate and never change, so they are kept on disk
+const DEFAULT_EXPORT_DIR: &str = "exports";
+/// Once exceeded, the oldest exports are deleted
+const DEFAULT_EXPORT_MAX_BYTES: u64 = 20 * 1024 * 1024 * 1024;
+
+const SQLITE_SCHEMA: &str = "
+	CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+	CREATE TABLE files (
+		id    INTEGER PRIMARY KEY,
+		vromf TEXT NOT NULL,
+		path  TEXT NOT NULL,
+		size  INTEGER NOT NULL
+	);
+	CREATE TABLE blk_values (
+		file_id    INTEGER NOT NULL REFERENCES files(id),
+		key_path   TEXT NOT NULL,
+		value_type TEXT NOT NULL,
+		value
+	);
+	CREATE TABLE lang (
+		key      TEXT NOT NULL,
+		file     TEXT NOT NULL,
+		language TEXT NOT NULL,
+		value    TEXT NOT NULL
+	);
+";
+
+// Created after inserting, which is a lot faster than maintaining them while inserting
+const SQLITE_INDICES: &str = "
+	CREATE INDEX files_path ON files(path);
+	CREATE INDEX blk_values_file ON blk_values(file_id);
+	CREATE INDEX blk_values_key_path ON blk_values(key_path);
+	CREATE INDEX lang_key ON lang(key);
+";
+
+/// Directory exports are cached in, configurable through EXPORT_DIR
+pub fn export_dir() -> PathBuf {
+	env::var("EXPORT_DIR")
+		.unwrap_or_else(|_| DEFAULT_EXPORT_DIR.to_owned())
+		.into()
+}
+
+/// Size limit of the export directory, configurable through EXPORT_MAX_BYTES
+fn export_max_bytes() -> u64 {
+	env::var("EXPORT_MAX_BYTES")
+		.ok()
+		.and_then(|max| max.parse().ok())
+		.unwrap_or(DEFAULT_EXPORT_MAX_BYTES)
+}
+
+/// Returns the path of a version's SQLite export, generating it first if it is not on disk yet
+pub async fn sqlite(state: Arc<AppState>, version: Version) -> ApiError<PathBuf> {
+	detached(async move {
+		let path = export_dir().join(format!("{version}.sqlite"));
+		let Some(_guard) = lock_missing(&state, &path).await else {
+			return Ok(path);
+		};
+
+		UnpackedVromfs::cache_unpacker(state.clone(), version, VromfType::Aces).await?;
+		let lang = LangTable::get(state.clone(), version).await?;
+		write_on_worker(state, &path, move |state, out| {
+			write_sqlite(state, version, &lang, out)
+		})
+		.await?;
+		Ok(path)
+	})
+	.await
+}
+
+/// Returns the path of a zip holding a version's Parquet tables, generating it first if it is not on disk yet
+pub async fn parquet(state: Arc<AppState>, version: Version) -> ApiError<PathBuf> {
+	detached(async move {
+		let path = export_dir().join(format!("{version}.parquet.zip"));
+		let Some(_guard) = lock_missing(&state, &path).await else {
+			return Ok(path);
+		};
+
+		UnpackedVromfs::cache_unpacker(state.clone(), version, VromfType::Aces).await?;
+		let units = UnitCatalog::get(state.clone(), version).await?;
+		let weapons = WeaponCatalog::get(state.clone(), version).await?;
+		write_on_worker(state, &path, move |state, out| {
+			write_parquet(state, version, &units, &weapons, out)
+		})
+		.await?;
+		Ok(path)
+	})
+	.await
+}
+
+/// Generates an export in its own task, so that it is completed and cleaned up even if the client disconnects
+async fn detached(
+	generate: impl Future<Output = ApiError<PathBuf>> + Send + 'static,
+) -> ApiError<PathBuf> {
+	tokio::spawn(generate).await.convert_err()?
+}
+
+/// Writes an export into a temporary file on the worker pool, only moving it into place once complete
+async fn write_on_worker(
+	state: Arc<AppState>,
+	path: &Path,
+	write: impl FnOnce(&AppState, &Path) -> ApiError<()> + Send + 'static,
+) -> ApiError<()> {
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir).convert_err()?;
+	}
+	let mut tmp = path.as_os_str().to_owned();
+	tmp.push(".tmp");
+	let tmp = PathBuf::from(tmp);
+	// Leftovers of an aborted export
+	let _ = fs::remove_file(&tmp);
+
+	let res = state
+		.clone()
+		.spawn_worker({
+			let tmp = tmp.clone();
+			move |s| {
+				let _ = s.send(write(&state, &tmp));
+			}
+		})
+		.await
+		.and_then(|res| res);
+	if res.is_err() {
+		let _ = fs::remove_file(&tmp);
+	}
+	res?;
+	fs::rename(&tmp, path).convert_err()?;
+	info!("Exported {}", path.display());
+
+	let path = path.to_owned();
+	let evicted = spawn_blocking(move || evict_exports(&path, export_max_bytes()))
+		.await
+		.unwrap_or_else(|e| Err(io::Error::other(e)));
+	if let Err(e) = evicted {
+		warn!("Failed to evict old exports. Reason: {e}");
+	}
+	Ok(())
+}
+
+/// Deletes the oldest exports next to the one just written until they fit into `max_bytes` together
+fn evict_exports(written: &Path, max_bytes: u64) -> io::Result<()> {
+	let Some(dir) = written.parent() else {
+		return Ok(());
+	};
+	let mut total = 0;
+	let mut exports = vec![];
+	for entry in fs::read_dir(dir)? {
+		let entry = entry?;
+		let meta = entry.metadata()?;
+		let path = entry.path();
+		// Temporary files belong to exports still being written
+		if !meta.is_file() || path.extension().is_some_and(|e| e == "tmp") {
+			continue;
+		}
+		total += meta.len();
+		if path != written {
+			exports.push((meta.modified()?, meta.len(), path));
+		}
+	}
+
+	exports.sort_unstable();
+	for (_, size, path) in exports {
+		if total <= max_bytes {
+			break;
+		}
+		info!("Evicting export {}", path.display());
+		fs::remove_file(&path)?;
+		total -= size;
+	}
+	Ok(())
+}
+
+/// Waits until no other task generates the export, returning None once it exists on disk
+async fn lock_missing(state: &AppState, path: &Path) -> Option<OwnedMutexGuard<()>> {
+	if path.exists() {
+		return None;
+	}
+	let lock = state
+		.export_locks
+		.entry(path.to_owned())
+		.or_insert_with(|| Arc::new(Mutex::new(())))
+		.clone();
+	let guard = lock.lock_owned().await;
+	// Another task might have finished the export while this one waited
+	(!path.exists()).then_some(guard)
+}
+
+/// Writes all files, BLK values and lang tables of a version, must be called from the worker pool
+fn write_sqlite(state: &AppState, version: Version, lang: &LangTable, out: &Path) -> ApiError<()> {
+	let mut conn = Connection::open(out).convert_err()?;
+	conn.execute_batch("PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF;")
+		.convert_err()?;
+	conn.execute_batch(SQLITE_SCHEMA).convert_err()?;
+
+	let tx = conn.transaction().convert_err()?;
+	{
+		let mut meta = tx
+			.prepare("INSERT INTO meta (key, value) VALUES (?1, ?2)")
+			.convert_err()?;
+		meta.execute(params!["version", version.to_string()])
+			.convert_err()?;
+
+		let mut insert_file = tx
+			.prepare("INSERT INTO files (vromf, path, size) VALUES (?1, ?2, ?3)")
+			.convert_err()?;
+		let mut insert_value = tx
+			.prepare(
+				"INSERT INTO blk_values (file_id, key_path, value_type, value) VALUES (?1, ?2, ?3, ?4)",
+			)
+			.convert_err()?;
+		for vromf in VromfType::VARIANTS {
+			UnpackedVromfs::for_each_file(
+				state,
+				version,
+				*vromf,
+				Some(BlkOutputFormat::Json),
+				|path, buf| {
+					insert_file
+						.execute(params![vromf.as_ref(), path, buf.len()])
+						.convert_err()?;
+					let file_id = tx.last_insert_rowid();
+
+					// BLKs which failed to decode are listed, but have no values
+					let Some(value) = is_blk(path)
+						.then(|| serde_json::from_slice::<Value>(&buf).ok())
+						.flatten()
+					else {
+						return Ok(());
+					};
+					let mut res = Ok(());
+					visit_leaves(&value, &mut |key_path, leaf| {
+						if res.is_ok() {
+							let (value_type, value) = sql_value(leaf);
+							res = insert_value
+								.execute(params![file_id, key_path, value_type, value])
+								.map(|_| ());
+						}
+					});
+					res.convert_err()
+				},
+			)?;
+		}
+
+		let mut insert_lang = tx
+			.prepare("INSERT INTO lang (key, file, language, value) VALUES (?1, ?2, ?3, ?4)")
+			.convert_err()?;
+		for (key, file, language, value) in lang.iter() {
+			insert_lang
+				.execute(params![key, file, language, value])
+				.convert_err()?;
+		}
+	}
+	tx.commit().convert_err()?;
+
+	conn.execute_batch(SQLITE_INDICES).convert_err()?;
+	conn.close().map_err(|(_, e)| e).convert_err()
+}
+
+/// Stores BLK leaves with their native SQLite type, next to the name of their JSON type
+fn sql_value(leaf: &Value) -> (&'static str, SqlValue) {
+	match leaf {
+		Value::Null => ("null", SqlValue::Null),
+		Value::Bool(b) => ("bool", SqlValue::Integer(i64::from(*b))),
+		Value::Number(n) => match n.as_i64() {
+			Some(i) => ("integer", SqlValue::Integer(i)),
+			None => ("float", SqlValue::Real(n.as_f64().unwrap_or_default())),
+		},
+		Value::String(s) => ("string", SqlValue::Text(s.clone())),
+		// visit_leaves only yields scalars
+		other => ("json", SqlValue::Text(other.to_string())),
+	}
+}
+
+/// Rows buffered before they are written as one Parquet row group
+const PARQUET_BATCH_ROWS: usize = 64 * 1024;
+
+/// Writes the tables blk_values, units and weapons into a zip, must be called from the worker pool
+fn write_parquet(
+	state: &AppState,
+	version: Version,
+	units: &UnitCatalog,
+	weapons: &WeaponCatalog,
+	out: &Path,
+) -> ApiError<()> {
+	let mut zip = ZipWriter::new(fs::File::create(out).convert_err()?);
+	// Parquet compresses its columns itself
+	let options = SimpleFileOptions::default()
+		.compression_method(CompressionMethod::Stored)
+		.large_file(true);
+
+	zip.start_file("blk_values.parquet", options)
+		.convert_err()?;
+	let schema = Arc::new(Schema::new(vec![
+		Field::new("file", DataType::Utf8, false),
+		Field::new("key_path", DataType::Utf8, false),
+		Field::new("value_type", DataType::Utf8, false),
+		Field::new("value", DataType::Utf8, true),
+	]));
+	let mut writer = parquet_writer(&mut zip, schema.clone())?;
+	let mut columns: [StringBuilder; 4] = Default::default();
+	let mut rows = 0;
+	for vromf in VromfType::VARIANTS {
+		UnpackedVromfs::for_each_file(
+			state,
+			version,
+			*vromf,
+			Some(BlkOutputFormat::Json),
+			|path, buf| {
+				let Some(value) = is_blk(path)
+					.then(|| serde_json::from_slice::<Value>(&buf).ok())
+					.flatten()
+				else {
+					return Ok(());
+				};
+				let file = format!("{vromf}/{path}");
+				visit_leaves(&value, &mut |key_path, leaf| {
+					let [file_col, key_col, type_col, value_col] = &mut columns;
+					file_col.append_value(&file);
+					key_col.append_value(key_path);
+					type_col.append_value(sql_value(leaf).0);
+					if leaf.is_null() {
+						value_col.append_null();
+					} else {
+						value_col.append_value(leaf_to_string(leaf));
+					}
+					rows += 1;
+				});
+				if rows >= PARQUET_BATCH_ROWS {
+					write_batch(&mut writer, &schema, columns.each_mut().map(finish))?;
+					rows = 0;
+				}
+				Ok(())
+			},
+		)?;
+	}
+	write_batch(&mut writer, &schema, columns.each_mut().map(finish))?;
+	writer.close().convert_err()?;
+
+	zip.start_file("units.parquet", options).convert_err()?;
+	let schema = Arc::new(Schema::new(vec![
+		Field::new("id", DataType::Utf8, false),
+		Field::new("country", DataType::Utf8, false),
+		Field::new("unit_class", DataType::Utf8, false),
+		Field::new("unit_type", DataType::Utf8, false),
+		Field::new("rank", DataType::UInt64, false),
+		Field::new("br_arcade", DataType::Float64, false),
+		Field::new("br_realistic", DataType::Float64, false),
+		Field::new("br_simulator", DataType::Float64, false),
+		Field::new("research_cost", DataType::UInt64, false),
+		Field::new("purchase_cost", DataType::UInt64, false),
+		Field::new("repair_cost_arcade", DataType::UInt64, false),
+		Field::new("repair_cost_realistic", DataType::UInt64, false),
+		Field::new("repair_cost_simulator", DataType::UInt64, false),
+		Field::new(
+			"weapon_presets",
+			DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+			false,
+		),
+	]));
+	let units = &units.units;
+	let strings = |f: fn(&Unit) -> &str| -> ArrayRef {
+		Arc::new(StringArray::from_iter_values(units.iter().map(f)))
+	};
+	let integers = |f: fn(&Unit) -> u64| -> ArrayRef {
+		Arc::new(UInt64Array::from_iter_values(units.iter().map(f)))
+	};
+	let floats = |f: fn(&Unit) -> f64| -> ArrayRef {
+		Arc::new(Float64Array::from_iter_values(units.iter().map(f)))
+	};
+	let mut presets = ListBuilder::new(StringBuilder::new());
+	for unit in units {
+		presets.append_value(unit.weapon_presets.iter().map(Some));
+	}
+	let mut writer = parquet_writer(&mut zip, schema.clone())?;
+	write_batch(
+		&mut writer,
+		&schema,
+		[
+			strings(|u| &u.id),
+			strings(|u| &u.country),
+			strings(|u| &u.unit_class),
+			Arc::new(StringArray::from_iter_values(
+				units.iter().map(|u| u.unit_type.to_string()),
+			)),
+			integers(|u| u.rank),
+			floats(|u| u.battle_ratings.arcade),
+			floats(|u| u.battle_ratings.realistic),
+			floats(|u| u.battle_ratings.simulator),
+			integers(|u| u.research_cost),
+			integers(|u| u.purchase_cost),
+			integers(|u| u.repair_costs.arcade),
+			integers(|u| u.repair_costs.realistic),
+			integers(|u| u.repair_costs.simulator),
+			Arc::new(presets.finish()),
+		],
+	)?;
+	writer.close().convert_err()?;
+
+	zip.start_file("weapons.parquet", options).convert_err()?;
+	let schema = Arc::new(Schema::new(vec![
+		Field::new("id", DataType::Utf8, false),
+		Field::new("path", DataType::Utf8, false),
+		Field::new("category", DataType::Utf8, false),
+		Field::new("kind", DataType::Utf8, false),
+		Field::new("caliber", DataType::Float64, true),
+		Field::new("mass", DataType::Float64, true),
+		Field::new("explosive_mass", DataType::Float64, true),
+		Field::new("explosive_type", DataType::Utf8, true),
+		Field::new("max_distance", DataType::Float64, true),
+		Field::new("speed", DataType::Float64, true),
+		Field::new("ammo_type", DataType::Utf8, true),
+	]));
+	let weapons = &weapons.weapons;
+	let strings = |f: fn(&Weapon) -> Option<&str>| -> ArrayRef {
+		Arc::new(StringArray::from_iter(weapons.iter().map(f)))
+	};
+	let floats = |f: fn(&Weapon) -> Option<f64>| -> ArrayRef {
+		Arc::new(Float64Array::from_iter(weapons.iter().map(f)))
+	};
+	let mut writer = parquet_writer(&mut zip, schema.clone())?;
+	write_batch(
+		&mut writer,
+		&schema,
+		[
+			strings(|w| Some(&w.id)),
+			strings(|w| Some(&w.path)),
+			strings(|w| Some(&w.category)),
+			Arc::new(StringArray::from_iter_values(
+				weapons.iter().map(|w| w.kind.to_string()),
+			)),
+			floats(|w| w.caliber),
+			floats(|w| w.mass),
+			floats(|w| w.explosive_mass),
+			strings(|w| w.explosive_type.as_deref()),
+			floats(|w| w.max_distance),
+			floats(|w| w.speed),
+			strings(|w| w.ammo_type.as_deref()),
+		],
+	)?;
+	writer.close().convert_err()?;
+
+	zip.finish().convert_err()?;
+	Ok(())
+}
+
+fn parquet_writer<W: Write + Send>(w: W, schema: SchemaRef) -> ApiError<ArrowWriter<W>> {
+	let props = WriterProperties::builder()
+		.set_compression(Compression::ZSTD(ZstdLevel::default()))
+		.build();
+	ArrowWriter::try_new(w, schema, Some(props)).convert_err()
+}
+
+fn write_batch<W: Write + Send, const N: usize>(
+	writer: &mut ArrowWriter<W>,
+	schema: &SchemaRef,
+	columns: [ArrayRef; N],
+) -> ApiError<()> {
+	let batch = RecordBatch::try_new(schema.clone(), columns.into()).convert_err()?;
+	writer.write(&batch).convert_err()
+}
+
+fn finish(builder: &mut StringBuilder) -> ArrayRef {
+	Arc::new(builder.finish())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::{Duration, SystemTime};
+
+	use super::*;
+
+	#[test]
+	fn evicts_oldest_exports_first() {
+		let dir = tempfile::tempdir().unwrap();
+		let now = SystemTime::now();
+		let write = |name: &str, age: u64| {
+			let path = dir.path().join(name);
+			fs::write(&path, [0; 10]).unwrap();
+			fs::File::options()
+				.write(true)
+				.open(&path)
+				.unwrap()
+				.set_modified(now - Duration::from_secs(age))
+				.unwrap();
+			path
+		};
+		write("2.39.0.1.sqlite", 30);
+		write("2.39.0.2.sqlite", 20);
+		write("2.39.0.3.sqlite.tmp", 40);
+		// Oldest of all, but just written
+		let written = write("2.39.0.4.parquet.zip", 50);
+
+		evict_exports(&written, 25).unwrap();
+		let mut left = fs::read_dir(dir.path())
+			.unwrap()
+			.map(|e| e.unwrap().file_name().into_string().unwrap())
+			.collect::<Vec<_>>();
+		left.sort();
+		assert_eq!(
+			left,
+			[
+				"2.39.0.2.sqlite",
+				"2.39.0.3.sqlite.tmp",
+				"2.39.0.4.parquet.zip"
+			]
+		);
+	}
+}
@@ -0,0 +1,166 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Arc};
+
+use serde::Deserialize;
+use tracing::warn;
+use wt_version::Version;
+
+use crate::{
+	app_state::AppState,
+	endpoints::get_vromfs::DATAMINE_REPO,
+	error::ApiError,
+	eyre_error_translation::EyreToApiError,
+	rate_limit::Priority,
+};
+
+/// Amount of most recent versions listed in the feed
+const FEED_SIZE: usize = 20;
+/// GitHub lists at most this many files on the first page of a commit
+const MAX_LISTED_FILES: usize = 300;
+
+/// Details of the datamine commit introducing a version
+pub struct FeedEntry {
+	pub version:   Version,
+	pub sha:       String,
+	/// RFC 3339 timestamp of the commit
+	pub committed: String,
+	/// Changed file count per top level folder, i.e. per unpacked vromf
+	pub changes:   BTreeMap<String, usize>,
+	/// Whether GitHub truncated the file listing
+	pub truncated: bool,
+}
+
+// Subset of https://docs.github.com/en/rest/commits/commits#get-a-commit
+#[derive(Deserialize)]
+struct Commit {
+	commit: CommitDetails,
+	#[serde(default)]
+	files:  Vec<CommitFile>,
+}
+
+#[derive(Deserialize)]
+struct CommitDetails {
+	committer: CommitSignature,
+}
+
+#[derive(Deserialize)]
+struct CommitSignature {
+	date: String,
+}
+
+#[derive(Deserialize)]
+struct CommitFile {
+	filename: String,
+}
+
+impl FeedEntry {
+	/// Returns the feed entry of a version, asking GitHub on first use
+	pub async fn get(state: Arc<AppState>, version: Version, sha: String) -> ApiError<Arc<Self>> {
+		if let Some(entry) = state.feed_entries.get(&version) {
+			return Ok(entry.clone());
+		}
+
+		let octo = &state.octocrab;
+		let route = format!("/repos/{DATAMINE_REPO}/commits/{sha}");
+		let commit = state
+			.rate_limit
+			.call::<Commit>(octo, Priority::User, &route)
+			.await?;
+
+		let mut changes = BTreeMap::<String, usize>::new();
+		for file in &commit.files {
+			let folder = file
+				.filename
+				.split_once('/')
+				.map_or(file.filename.as_str(), |(folder, _)| folder);
+			*changes.entry(folder.to_owned()).or_default() += 1;
+		}
+		let entry = Arc::new(Self {
+			version,
+			sha,
+			committed: commit.commit.committer.date,
+			truncated: commit.files.len() >= MAX_LISTED_FILES,
+			changes,
+		});
+
+		// Commits never change, so entries stay valid forever
+		state.feed_entries.insert(version, entry.clone());
+		Ok(entry)
+	}
+
+	fn write_atom(&self, feed: &mut String) -> std::fmt::Result {
+		let mut summary = self
+			.changes
+			.iter()
+			.map(|(folder, count)| format!("{folder}: {count} changed files"))
+			.collect::<Vec<_>>()
+			.join("\n");
+		if self.changes.is_empty() {
+			summary.push_str("No changed files");
+		}
+		if self.truncated {
+			summary.push_str(&format!(
+				"\nGitHub lists only the first {MAX_LISTED_FILES} changed files"
+			));
+		}
+
+		writeln!(feed, "<entry>")?;
+		writeln!(feed, "<id>urn:wt-dm-api:version:{}</id>", self.version)?;
+		writeln!(feed, "<title>Version {}</title>", self.version)?;
+		writeln!(feed, "<updated>{}</updated>", escape(&self.committed))?;
+		writeln!(
+			feed,
+			r#"<link rel="alternate" href="https://github.com/{DATAMINE_REPO}/commit/{}"/>"#,
+			escape(&self.sha)
+		)?;
+		writeln!(feed, "<summary>{}</summary>", escape(&summary))?;
+		writeln!(feed, "</entry>")
+	}
+}
+
+/// Atom feed of the most recent versions, newest first
+pub async fn atom(state: Arc<AppState>) -> ApiError<String> {
+	let mut versions = state
+		.vromf_cache
+		.list_versions()
+		.map(|e| (*e.key(), e.value().clone()))
+		.collect::<Vec<_>>();
+	versions.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+	versions.truncate(FEED_SIZE);
+
+	let mut entries = vec![];
+	for (version, sha) in versions {
+		match FeedEntry::get(state.clone(), version, sha).await {
+			Ok(entry) => entries.push(entry),
+			// A missing entry is picked up by the next request, the rest of the feed stays usable
+			Err(e) => warn!("Leaving {version} out of the feed. Reason: {}", e.1),
+		}
+	}
+
+	let updated = entries
+		.first()
+		.map_or("1970-01-01T00:00:00Z", |e| e.committed.as_str());
+	let mut feed = String::new();
+	writeln!(feed, r#"<?xml version="1.0" encoding="utf-8"?>"#).convert_err()?;
+	writeln!(feed, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#).convert_err()?;
+	writeln!(feed, "<id>urn:wt-dm-api:versions</id>").convert_err()?;
+	writeln!(feed, "<title>War Thunder datamine versions</title>").convert_err()?;
+	writeln!(feed, "<updated>{}</updated>", escape(updated)).convert_err()?;
+	writeln!(feed, "<author><name>War Thunder Datamine</name></author>").convert_err()?;
+	writeln!(
+		feed,
+		r#"<link rel="alternate" href="https://github.com/{DATAMINE_REPO}"/>"#
+	)
+	.convert_err()?;
+	for entry in entries {
+		entry.write_atom(&mut feed).convert_err()?;
+	}
+	writeln!(feed, "</feed>").convert_err()?;
+	Ok(feed)
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+}
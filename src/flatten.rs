@@ -0,0 +1,142 @@
+use std::{borrow::Cow, fmt::Write};
+
+use serde_json::Value;
+
+/// Calls `f` for every leaf of a decoded BLK with its key path, such as `rocket.maxDistance` or `Weapon[2].blk`
+pub fn visit_leaves(value: &Value, f: &mut impl FnMut(&str, &Value)) {
+	let mut path = String::new();
+	visit(value, &mut path, f);
+}
+
+fn visit(value: &Value, path: &mut String, f: &mut impl FnMut(&str, &Value)) {
+	let len = path.len();
+	match value {
+		Value::Object(map) => {
+			for (key, value) in map {
+				if !path.is_empty() {
+					path.push('.');
+				}
+				path.push_str(key);
+				visit(value, path, f);
+				path.truncate(len);
+			}
+		},
+		Value::Array(values) => {
+			for (i, value) in values.iter().enumerate() {
+				write!(path, "[{i}]").expect("writing to string is infallible");
+				visit(value, path, f);
+				path.truncate(len);
+			}
+		},
+		leaf => f(path, leaf),
+	}
+}
+
+/// Renders a leaf without the quotes JSON would put around strings
+pub fn leaf_to_string(value: &Value) -> Cow<'_, str> {
+	match value {
+		Value::String(s) => Cow::Borrowed(s),
+		other => Cow::Owned(other.to_string()),
+	}
+}
+
+/// Selects a subtree by JSON pointer (`/rocket/maxDistance`, the leading slash is optional) or by
+/// the dotted key paths produced by [`visit_leaves`] (`rocket.maxDistance`, `Weapon[2].blk`)
+pub fn select<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+	if path.contains('/') {
+		return if path.starts_with('/') {
+			value.pointer(path)
+		} else {
+			value.pointer(&format!("/{path}"))
+		};
+	}
+
+	let mut current = value;
+	for segment in path.split('.').filter(|s| !s.is_empty()) {
+		let (key, mut indices) = segment.split_at(segment.find('[').unwrap_or(segment.len()));
+		if !key.is_empty() {
+			current = match current {
+				Value::Object(map) => map.get(key)?,
+				Value::Array(values) => values.get(key.parse::<usize>().ok()?)?,
+				_ => return None,
+			};
+		}
+		while let Some(rest) = indices.strip_prefix('[') {
+			let (index, after) = rest.split_once(']')?;
+			current = current.get(index.parse::<usize>().ok()?)?;
+			indices = after;
+		}
+		if !indices.is_empty() {
+			return None;
+		}
+	}
+	Some(current)
+}
+
+/// Renders every leaf as `path.to.key = value` on its own line
+pub fn to_flat_lines(value: &Value) -> String {
+	let mut out = String::new();
+	visit_leaves(value, &mut |key_path, leaf| {
+		if key_path.is_empty() {
+			// A selected scalar has no key of its own
+			writeln!(out, "{}", leaf_to_string(leaf))
+		} else {
+			writeln!(out, "{key_path} = {}", leaf_to_string(leaf))
+		}
+		.expect("writing to string is infallible");
+	});
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::json;
+
+	use super::*;
+
+	fn sample() -> Value {
+		json!({
+			"rocket": { "maxDistance": 8000.0, "guidance": { "type": "ir" } },
+			"Weapon": [{ "blk": "a.blk" }, { "blk": "b.blk" }, { "blk": "c.blk" }],
+			"matrix": [[1, 2], [3, 4]],
+		})
+	}
+
+	#[test]
+	fn select_dotted_key_paths() {
+		let value = sample();
+		assert_eq!(select(&value, "rocket.maxDistance"), Some(&json!(8000.0)));
+		assert_eq!(select(&value, "rocket.guidance.type"), Some(&json!("ir")));
+		assert_eq!(select(&value, "Weapon[2].blk"), Some(&json!("c.blk")));
+		assert_eq!(select(&value, "matrix[1][0]"), Some(&json!(3)));
+		assert_eq!(select(&value, "rocket"), value.get("rocket"));
+		assert_eq!(select(&value, ""), Some(&value));
+	}
+
+	#[test]
+	fn select_json_pointers() {
+		let value = sample();
+		assert_eq!(select(&value, "/rocket/maxDistance"), Some(&json!(8000.0)));
+		assert_eq!(select(&value, "rocket/maxDistance"), Some(&json!(8000.0)));
+		assert_eq!(select(&value, "/Weapon/1/blk"), Some(&json!("b.blk")));
+	}
+
+	#[test]
+	fn select_missing_paths() {
+		let value = sample();
+		assert_eq!(select(&value, "rocket.minDistance"), None);
+		assert_eq!(select(&value, "Weapon[3].blk"), None);
+		assert_eq!(select(&value, "Weapon[x]"), None);
+		assert_eq!(select(&value, "Weapon[0"), None);
+		assert_eq!(select(&value, "rocket.maxDistance.value"), None);
+		assert_eq!(select(&value, "/rocket/minDistance"), None);
+	}
+
+	#[test]
+	fn select_matches_visited_key_paths() {
+		let value = sample();
+		visit_leaves(&value, &mut |key_path, leaf| {
+			assert_eq!(select(&value, key_path), Some(leaf), "{key_path}");
+		});
+	}
+}
@@ -0,0 +1,218 @@
+use std::io::{Cursor, Read};
+
+use flate2::read::ZlibDecoder;
+use http::StatusCode;
+use image::{codecs::dds::DdsDecoder, DynamicImage, ImageFormat, ImageReader, Limits, RgbaImage};
+
+use crate::error::ApiError;
+
+/// Extensions of textures that can be converted to PNG
+const IMAGE_EXTENSIONS: &[&str] = &[".ddsx", ".dds", ".tga", ".png", ".jpg", ".jpeg", ".bmp"];
+/// Largest width or height accepted, GUI textures stay well below this
+const MAX_DIMENSION: u32 = 8192;
+/// Upper bound for the memory used while decoding one image
+const MAX_DECODED_SIZE: u64 = 256 * 1024 * 1024;
+
+// Dagor engine DDSx header, see ddsxTex.h
+const DDSX_MAGIC: &[u8; 4] = b"DDSx";
+const DDSX_HEADER_SIZE: usize = 32;
+const DDSX_FLG_CUBTEX: u32 = 0x0800;
+const DDSX_FLG_VOLTEX: u32 = 0x1000;
+const DDSX_FLG_REV_MIP_ORDER: u32 = 0x0004_0000;
+const DDSX_COMPRESSION_MASK: u32 = 0xE000_0000;
+const DDSX_ZSTD: u32 = 0x2000_0000;
+const DDSX_7ZIP: u32 = 0x4000_0000;
+const DDSX_OODLE: u32 = 0x6000_0000;
+const DDSX_ZLIB: u32 = 0x8000_0000;
+/// D3DFMT_A8R8G8B8, stored as BGRA bytes
+const D3DFMT_A8R8G8B8: u32 = 21;
+
+pub fn is_image(path: &str) -> bool {
+	let path = path.to_ascii_lowercase();
+	IMAGE_EXTENSIONS.iter().any(|ext| path.ends_with(ext))
+}
+
+/// Decodes a texture and re-encodes it as PNG
+pub fn to_png(path: &str, buf: &[u8]) -> ApiError<Vec<u8>> {
+	let unsupported = |e: String| {
+		(
+			StatusCode::UNPROCESSABLE_ENTITY,
+			format!("{path} cannot be converted to PNG: {e}"),
+		)
+	};
+	let is_ddsx = buf.starts_with(DDSX_MAGIC);
+
+	// Checked before decoding, so that oversized images never allocate their pixels
+	let (width, height) = if is_ddsx {
+		ddsx_dimensions(buf)
+	} else {
+		reader(path, buf).and_then(|r| r.into_dimensions().map_err(|e| e.to_string()))
+	}
+	.map_err(unsupported)?;
+	if width > MAX_DIMENSION || height > MAX_DIMENSION {
+		return Err((
+			StatusCode::PAYLOAD_TOO_LARGE,
+			format!(
+				"{path} is {width}x{height}, images are limited to {MAX_DIMENSION}x{MAX_DIMENSION}"
+			),
+		));
+	}
+
+	let image = if is_ddsx {
+		decode_ddsx(buf)
+	} else {
+		decode(path, buf)
+	}
+	.map_err(unsupported)?;
+
+	let mut out = Cursor::new(vec![]);
+	image
+		.write_to(&mut out, ImageFormat::Png)
+		.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+	Ok(out.into_inner())
+}
+
+fn limits() -> Limits {
+	let mut limits = Limits::default();
+	limits.max_image_width = Some(MAX_DIMENSION);
+	limits.max_image_height = Some(MAX_DIMENSION);
+	limits.max_alloc = Some(MAX_DECODED_SIZE);
+	limits
+}
+
+fn reader<'a>(path: &str, buf: &'a [u8]) -> Result<ImageReader<Cursor<&'a [u8]>>, String> {
+	match ImageFormat::from_path(path) {
+		Ok(format) => Ok(ImageReader::with_format(Cursor::new(buf), format)),
+		// TGA has no magic bytes, so the extension is preferred over guessing
+		Err(_) => ImageReader::new(Cursor::new(buf))
+			.with_guessed_format()
+			.map_err(|e| e.to_string()),
+	}
+}
+
+fn decode(path: &str, buf: &[u8]) -> Result<DynamicImage, String> {
+	let mut reader = reader(path, buf)?;
+	reader.limits(limits());
+	reader.decode().map_err(|e| e.to_string())
+}
+
+fn ddsx_dimensions(buf: &[u8]) -> Result<(u32, u32), String> {
+	if buf.len() < DDSX_HEADER_SIZE {
+		return Err("truncated DDSx header".to_owned());
+	}
+	Ok((u32::from(u16_at(buf, 12)), u32::from(u16_at(buf, 14))))
+}
+
+fn decode_ddsx(buf: &[u8]) -> Result<DynamicImage, String> {
+	let (width, height) = ddsx_dimensions(buf)?;
+	let format = u32_at(buf, 4);
+	let flags = u32_at(buf, 8);
+	let mem_size = u32_at(buf, 24) as usize;
+	let packed_size = u32_at(buf, 28) as usize;
+
+	if flags & (DDSX_FLG_CUBTEX | DDSX_FLG_VOLTEX) != 0 {
+		return Err("cube and volume textures are not supported".to_owned());
+	}
+	if mem_size as u64 > MAX_DECODED_SIZE {
+		return Err(format!(
+			"texture data of {mem_size} bytes exceeds the size limit"
+		));
+	}
+
+	let body = &buf[DDSX_HEADER_SIZE..];
+	let body = &body[..packed_size.min(body.len())];
+	let mut data = Vec::with_capacity(mem_size);
+	match flags & DDSX_COMPRESSION_MASK {
+		0 => data.extend_from_slice(body),
+		DDSX_ZSTD => {
+			zstd::Decoder::new(body)
+				.map_err(|e| e.to_string())?
+				.take(mem_size as u64)
+				.read_to_end(&mut data)
+				.map_err(|e| e.to_string())?;
+		},
+		DDSX_ZLIB => {
+			ZlibDecoder::new(body)
+				.take(mem_size as u64)
+				.read_to_end(&mut data)
+				.map_err(|e| e.to_string())?;
+		},
+		DDSX_7ZIP => return Err("7zip compressed DDSx is not supported".to_owned()),
+		DDSX_OODLE => return Err("oodle compressed DDSx is not supported".to_owned()),
+		other => return Err(format!("unknown DDSx compression {other:#x}")),
+	}
+
+	let (fourcc, top_mip_size) = match &format.to_le_bytes() {
+		fourcc @ (b"DXT1" | b"DXT3" | b"DXT5") => {
+			let block_size = if fourcc == b"DXT1" { 8 } else { 16 };
+			let blocks = width.div_ceil(4) as usize * height.div_ceil(4) as usize;
+			(Some(*fourcc), blocks * block_size)
+		},
+		_ if format == D3DFMT_A8R8G8B8 => (None, width as usize * height as usize * 4),
+		_ => return Err(format!("texture format {format:#x} is not supported")),
+	};
+	if data.len() < top_mip_size {
+		return Err("DDSx holds less data than its largest mip needs".to_owned());
+	}
+	// Mips are stored largest first, unless flagged otherwise
+	let top_mip = if flags & DDSX_FLG_REV_MIP_ORDER != 0 {
+		&data[data.len() - top_mip_size..]
+	} else {
+		&data[..top_mip_size]
+	};
+
+	match fourcc {
+		Some(fourcc) => {
+			let mut dds = dds_header(width, height, fourcc);
+			dds.extend_from_slice(top_mip);
+			let decoder = DdsDecoder::new(Cursor::new(dds)).map_err(|e| e.to_string())?;
+			DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())
+		},
+		None => {
+			let rgba = top_mip
+				.chunks_exact(4)
+				.flat_map(|bgra| [bgra[2], bgra[1], bgra[0], bgra[3]])
+				.collect();
+			RgbaImage::from_raw(width, height, rgba)
+				.map(DynamicImage::ImageRgba8)
+				.ok_or_else(|| "texture data does not match its dimensions".to_owned())
+		},
+	}
+}
+
+fn u32_at(buf: &[u8], i: usize) -> u32 {
+	u32::from_le_bytes(buf[i..i + 4].try_into().expect("slice of 4 bytes"))
+}
+
+fn u16_at(buf: &[u8], i: usize) -> u16 {
+	u16::from_le_bytes(buf[i..i + 2].try_into().expect("slice of 2 bytes"))
+}
+
+/// Minimal DDS header for a single mip of a block compressed texture
+fn dds_header(width: u32, height: u32, fourcc: [u8; 4]) -> Vec<u8> {
+	const DDSD_CAPS_HEIGHT_WIDTH_PIXELFORMAT: u32 = 0x1 | 0x2 | 0x4 | 0x1000;
+	const DDPF_FOURCC: u32 = 0x4;
+	const DDSCAPS_TEXTURE: u32 = 0x1000;
+
+	let mut header = Vec::with_capacity(128);
+	header.extend_from_slice(b"DDS ");
+	for field in [
+		124,
+		DDSD_CAPS_HEIGHT_WIDTH_PIXELFORMAT,
+		height,
+		width,
+		0,
+		0,
+		1,
+	] {
+		header.extend_from_slice(&u32::to_le_bytes(field));
+	}
+	header.extend_from_slice(&[0; 4 * 11]);
+	header.extend_from_slice(&u32::to_le_bytes(32));
+	header.extend_from_slice(&u32::to_le_bytes(DDPF_FOURCC));
+	header.extend_from_slice(&fourcc);
+	header.extend_from_slice(&[0; 4 * 5]);
+	header.extend_from_slice(&u32::to_le_bytes(DDSCAPS_TEXTURE));
+	header.extend_from_slice(&[0; 4 * 4]);
+	header
+}
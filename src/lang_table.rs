@@ -0,0 +1,247 @@
+use std::{
+	collections::{BTreeMap, HashMap},
+	path::Path,
+	sync::Arc,
+};
+
+use http::StatusCode;
+use serde::Serialize;
+use tracing::warn;
+use utoipa::ToSchema;
+use wt_version::Version;
+
+use crate::{
+	app_state::AppState,
+	endpoints::files::UnpackedVromfs,
+	error::ApiError,
+	eyre_error_translation::EyreToApiError,
+	vromf_enum::VromfType,
+};
+
+/// Columns of the lang CSVs which do not hold translations
+const NON_LANGUAGE_COLUMNS: &[&str] = &["Comments", "max_chars"];
+
+/// All localization keys of a version's lang.vromfs.bin, merged across its CSVs
+#[derive(Default)]
+pub struct LangTable {
+	languages: Vec<String>,
+	entries:   HashMap<String, LangEntry>,
+}
+
+struct LangEntry {
+	file:   String,
+	// Indexed like LangTable::languages
+	values: Vec<Option<String>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LocalizedKey {
+	key:    String,
+	/// CSV the key is defined in
+	file:   String,
+	/// Translation per language
+	values: BTreeMap<String, String>,
+}
+
+impl LangTable {
+	/// Returns the parsed lang tables of a version, parsing them on first use
+	pub async fn get(state: Arc<AppState>, version: Version) -> ApiError<Arc<Self>> {
+		if let Some(table) = state.lang_tables.get(&version) {
+			return Ok(table.clone());
+		}
+
+		let paths = UnpackedVromfs::file_index(state.clone(), version, VromfType::Lang)
+			.await?
+			.iter()
+			.filter(|e| e.path.ends_with(".csv"))
+			.map(|e| e.path.clone())
+			.collect::<Vec<_>>();
+		let table = state
+			.clone()
+			.spawn_worker(move |s| {
+				let res = || {
+					let unpacker = state.unpacked_vromfs.unpacker(version, VromfType::Lang)?;
+
+					let mut table = Self::default();
+					for path in paths {
+						let (_, buf) = unpacker
+							.unpack_one(Path::new(&path), None, false)
+							.convert_err()?
+							.split();
+						if let Err(e) = table.add_csv(&path, &buf) {
+							warn!("Skipping malformed lang file {path}. Reason: {e}");
+						}
+					}
+					Ok(Arc::new(table))
+				};
+				let _ = s.send(res());
+			})
+			.await??;
+
+		state.lang_tables.insert(version, table.clone());
+		Ok(table)
+	}
+
+	fn add_csv(&mut self, path: &str, buf: &[u8]) -> csv::Result<()> {
+		let mut reader = csv::ReaderBuilder::new()
+			.delimiter(b';')
+			.flexible(true)
+			.from_reader(buf);
+
+		// The first column holds the key, every other one is named like <English>
+		let columns = reader
+			.headers()?
+			.iter()
+			.skip(1)
+			.map(|header| {
+				let name = header.trim_matches(|c| c == '<' || c == '>');
+				(!NON_LANGUAGE_COLUMNS.contains(&name)).then(|| self.language_column(name))
+			})
+			.collect::<Vec<_>>();
+
+		// A malformed record only loses its own key, and not the rest of the file
+		for record in reader.records() {
+			let record = match record {
+				Ok(record) => record,
+				Err(e) => {
+					warn!("Skipping malformed record in {path}. Reason: {e}");
+					continue;
+				},
+			};
+			let Some(key) = record.get(0).filter(|k| !k.is_empty()) else {
+				continue;
+			};
+			let entry = self
+				.entries
+				.entry(key.to_owned())
+				.or_insert_with(|| LangEntry {
+					file:   path.to_owned(),
+					values: vec![],
+				});
+			for (value, column) in record.iter().skip(1).zip(&columns) {
+				let Some(column) = *column else {
+					continue;
+				};
+				if value.is_empty() {
+					continue;
+				}
+				if entry.values.len() <= column {
+					entry.values.resize(column + 1, None);
+				}
+				entry.values[column] = Some(value.to_owned());
+			}
+		}
+		Ok(())
+	}
+
+	fn language_column(&mut self, name: &str) -> usize {
+		if let Some(i) = self.languages.iter().position(|l| l == name) {
+			return i;
+		}
+		self.languages.push(name.to_owned());
+		self.languages.len() - 1
+	}
+
+	/// Resolves a language name case-insensitively
+	pub fn language(&self, name: &str) -> ApiError<usize> {
+		self.languages
+			.iter()
+			.position(|l| l.eq_ignore_ascii_case(name))
+			.ok_or_else(|| {
+				(
+					StatusCode::BAD_REQUEST,
+					format!(
+						"Unknown language {name}, expected one of: {}",
+						self.languages.join(", ")
+					),
+				)
+			})
+	}
+
+	/// Translation of a key in one language
+	pub fn localize(&self, key: &str, language: usize) -> Option<&str> {
+		self.entries.get(key)?.values.get(language)?.as_deref()
+	}
+
+	/// Every translation as (key, file, language, value)
+	pub fn iter(&self) -> impl Iterator<Item = (&str, &str, &str, &str)> {
+		self.entries.iter().flat_map(move |(key, entry)| {
+			entry.values.iter().enumerate().filter_map(move |(i, v)| {
+				Some((
+					key.as_str(),
+					entry.file.as_str(),
+					self.languages[i].as_str(),
+					v.as_deref()?,
+				))
+			})
+		})
+	}
+
+	pub fn lookup(&self, key: &str, language: Option<usize>) -> Option<LocalizedKey> {
+		self.entries
+			.get(key)
+			.map(|entry| self.to_localized(key, entry, language))
+	}
+
+	/// Case-insensitive substring search over keys and translations
+	pub fn search(&self, query: &str, language: Option<usize>, limit: usize) -> Vec<LocalizedKey> {
+		let query = query.to_lowercase();
+		let mut matches = self
+			.entries
+			.iter()
+			.filter(|(key, entry)| {
+				key.to_lowercase().contains(&query)
+					|| entry
+						.values
+						.iter()
+						.enumerate()
+						.filter(|(i, _)| language.map_or(true, |l| l == *i))
+						.filter_map(|(_, v)| v.as_deref())
+						.any(|v| v.to_lowercase().contains(&query))
+			})
+			.collect::<Vec<_>>();
+		// HashMap order is random, sort for stable pagination
+		matches.sort_unstable_by(|a, b| a.0.cmp(b.0));
+		matches
+			.into_iter()
+			.take(limit)
+			.map(|(key, entry)| self.to_localized(key, entry, language))
+			.collect()
+	}
+
+	fn to_localized(&self, key: &str, entry: &LangEntry, language: Option<usize>) -> LocalizedKey {
+		LocalizedKey {
+			key:    key.to_owned(),
+			file:   entry.file.clone(),
+			values: entry
+				.values
+				.iter()
+				.enumerate()
+				.filter(|(i, _)| language.map_or(true, |l| l == *i))
+				.filter_map(|(i, v)| Some((self.languages[i].clone(), v.clone()?)))
+				.collect(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn malformed_records_are_skipped() {
+		let mut table = LangTable::default();
+		table
+			.add_csv(
+				"lang/units.csv",
+				b"<ID|readonly|noverify>;<English>;<French>\nfirst;One;Un\nbad;\xff\xfe;x\nlast;Three;Trois\n",
+			)
+			.unwrap();
+
+		let english = table.language("English").unwrap();
+		let french = table.language("French").unwrap();
+		assert_eq!(table.localize("first", english), Some("One"));
+		assert_eq!(table.localize("bad", english), None);
+		assert_eq!(table.localize("last", french), Some("Trois"));
+	}
+}
@@ -1,18 +1,33 @@
 mod app_state;
+mod archive;
+mod cli;
+mod content_index;
 mod endpoints;
 mod error;
+mod events;
+mod export;
 mod eyre_error_translation;
+mod feed;
+mod flatten;
+mod image_convert;
+mod lang_table;
+mod output_format;
+mod rate_limit;
+mod unit_catalog;
 mod vromf_enum;
 mod wait_ready;
+mod weapon_catalog;
+mod webhooks;
 
 use std::{
+	env,
 	process::{abort, exit},
 	sync::Arc,
 	time::Duration,
 };
 
 use app_state::cache_refresh_task;
-use axum::{response::Redirect, routing::get, Router};
+use axum::{middleware, response::Redirect, routing::get, Router};
 use endpoints::{
 	files::{Params, __path_get_files, get_files, FileRequest, UnpackedVromfs},
 	get_vromfs::{get_latest, print_latest_version, VromfCache},
@@ -34,22 +49,68 @@ use wt_version::Version;
 use crate::{
 	app_state::AppState,
 	endpoints::{
+		events::{
+			__path_version_events,
+			__path_version_events_ws,
+			version_events,
+			version_events_ws,
+		},
+		export::{__path_export_parquet, __path_export_version, export_parquet, export_version},
+		feed::{__path_atom_feed, atom_feed},
 		get_vromfs::find_version_sha,
 		health::{__path_health, health},
+		lang::{__path_get_lang_key, get_lang_key},
+		query::{self, __path_query, query},
+		search::{
+			__path_search_content,
+			__path_search_lang,
+			__path_search_paths,
+			search_content,
+			search_lang,
+			search_paths,
+		},
+		units::{__path_list_units, __path_unit_history, list_units, unit_history},
 		versions::{__path_list_versions, list_versions},
+		weapons::{__path_list_weapons, list_weapons},
+		webhooks::{__path_webhook_deliveries, webhook_deliveries},
 	},
+	rate_limit::Priority,
 	wait_ready::WaitReady,
 };
 
 #[derive(OpenApi)]
 #[openapi(
-	paths(get_files, health, list_versions),
+	paths(
+		get_files,
+		health,
+		list_versions,
+		search_paths,
+		search_content,
+		query,
+		get_lang_key,
+		search_lang,
+		list_units,
+		unit_history,
+		list_weapons,
+		export_version,
+		export_parquet,
+		version_events,
+		version_events_ws,
+		webhook_deliveries,
+		atom_feed
+	),
 	info(title = "WT Datamining API", version = "1.0")
 )]
 struct ApiDoc;
 
 #[tokio::main]
 async fn main() {
+	let args = env::args().skip(1).collect::<Vec<_>>();
+	// Checked first, as the worker answers on stdout where logs would otherwise end up
+	if args.first().map(String::as_str) == Some(query::WORKER_ARG) {
+		exit(query::run_worker());
+	}
+
 	if cfg!(feature = "tokio-console") {
 		#[cfg(feature = "tokio-console")]
 		console_subscriber::init();
@@ -60,6 +121,10 @@ async fn main() {
 
 	color_eyre::install().unwrap(/*fine*/);
 
+	if !args.is_empty() {
+		exit(cli::run(Arc::new(AppState::default()), &args).await);
+	}
+
 	let t = spawn(async {
 		signal::ctrl_c().await.unwrap(/*fine*/);
 		error!("Got CTRL-C signal. Aborting in 1000ms");
@@ -79,25 +144,47 @@ async fn main() {
 		.route("/files/*path", get(get_files))
 		.route("/health", get(health))
 		.route("/metadata/versions", get(list_versions))
+		.route("/search/paths", get(search_paths))
+		.route("/search/content", get(search_content))
+		.route("/query", get(query))
+		.route("/lang/:version/*key", get(get_lang_key))
+		.route("/search/lang", get(search_lang))
+		.route("/units", get(list_units))
+		.route("/units/:id/history", get(unit_history))
+		.route("/weapons", get(list_weapons))
+		.route("/export/:file", get(export_version))
+		.route("/export/:file/parquet", get(export_parquet))
+		.route("/events", get(version_events))
+		.route("/events/ws", get(version_events_ws))
+		.route("/webhooks/deliveries", get(webhook_deliveries))
+		.route("/feed.atom", get(atom_feed))
 		.merge(Scalar::with_url("/docs", ApiDoc::openapi()))
+		.layer(middleware::from_fn_with_state(
+			state.clone(),
+			rate_limit::retry_after_header,
+		))
 		.with_state(state.clone());
 
 	// run our app with hyper, listening globally on port 3000
 	let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap(/*fine*/);
 
+	// Subscribed before the first refresh, so that no discovery is missed
+	webhooks::spawn_dispatcher(state.clone());
 	cache_refresh_task(state.clone(), wait_ready.register().await);
 
 	// Ensure the commit cache is filled from the latest version to the latest in assets/commits.txt
-	let mut octo = state.octocrab.lock().await;
-	find_version_sha(
+	state.rate_limit.refresh(&state.octocrab).await;
+	// Versions missing here are looked up again by the requests needing them
+	if let Err(e) = find_version_sha(
 		state.clone(),
 		&mut Some(Version::new(u16::MAX, u16::MAX, u16::MAX, u16::MAX)),
-		&mut octo,
+		Priority::Background,
 		None,
 	)
 	.await
-	.unwrap();
-	drop(octo);
+	{
+		error!("Failed to fill the commit cache. Reason: {}", e.1);
+	}
 
 	wait_ready.wait_ready().await;
 	info!("Wait ready completed. Starting server...");
@@ -0,0 +1,142 @@
+use std::{borrow::Cow, path::Path};
+
+use http::StatusCode;
+use serde_json::Value;
+use wt_blk::vromf::BlkOutputFormat;
+
+use crate::{
+	error::ApiError,
+	flatten::to_flat_lines,
+	image_convert::{self, is_image},
+};
+
+/// Format BLK files are returned in, every format past JSON is converted from the JSON representation
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy, strum::Display, strum::EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum OutputFormat {
+	#[strum(serialize = "raw")]
+	Raw,
+	#[strum(serialize = "blk")]
+	Blk,
+	#[strum(serialize = "json")]
+	Json,
+	#[strum(serialize = "yaml", serialize = "yml")]
+	Yaml,
+	#[strum(serialize = "toml")]
+	Toml,
+	#[strum(serialize = "msgpack", serialize = "messagepack")]
+	MsgPack,
+	#[strum(serialize = "cbor")]
+	Cbor,
+	/// One `path.to.key = value` line per leaf, or a `path,key,value` CSV for folders
+	#[strum(serialize = "flat")]
+	Flat,
+	/// Converts textures to PNG, any other file is returned raw
+	#[strum(serialize = "png")]
+	Png,
+}
+
+impl OutputFormat {
+	/// Format wt_blk has to unpack into, None if raw file
+	pub fn unpack_format(self) -> Option<BlkOutputFormat> {
+		match self {
+			OutputFormat::Raw | OutputFormat::Png => None,
+			OutputFormat::Blk => Some(BlkOutputFormat::BlkText),
+			_ => Some(BlkOutputFormat::Json),
+		}
+	}
+
+	pub fn content_type(self, path: &str) -> &'static str {
+		if self == OutputFormat::Png && is_image(path) {
+			return "image/png";
+		}
+		if !is_blk(path) {
+			return "application/octet-stream";
+		}
+		match self {
+			OutputFormat::Raw | OutputFormat::Png => "application/octet-stream",
+			OutputFormat::Blk | OutputFormat::Flat => "text/plain",
+			OutputFormat::Json => "application/json",
+			OutputFormat::Yaml => "application/yaml",
+			OutputFormat::Toml => "application/toml",
+			OutputFormat::MsgPack => "application/msgpack",
+			OutputFormat::Cbor => "application/cbor",
+		}
+	}
+
+	/// Converts an unpacked file into this format, leaving anything but BLK or images untouched
+	pub fn convert(self, path: &str, buf: Vec<u8>) -> ApiError<Vec<u8>> {
+		if self == OutputFormat::Png && is_image(path) {
+			return image_convert::to_png(path, &buf);
+		}
+		if !is_blk(path) {
+			return Ok(buf);
+		}
+		match self {
+			OutputFormat::Raw | OutputFormat::Blk | OutputFormat::Json | OutputFormat::Png => {
+				Ok(buf)
+			},
+			_ => {
+				let value = serde_json::from_slice::<Value>(&buf).map_err(|e| {
+					(
+						StatusCode::INTERNAL_SERVER_ERROR,
+						format!("{path} did not unpack to valid JSON: {e}"),
+					)
+				})?;
+				self.serialize(&value)
+					.map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, format!("{path}: {e}")))
+			},
+		}
+	}
+
+	/// Serializes an already decoded BLK, also used for partial BLKs
+	pub fn serialize(self, value: &Value) -> Result<Vec<u8>, String> {
+		match self {
+			OutputFormat::Raw | OutputFormat::Blk | OutputFormat::Json => {
+				serde_json::to_vec(value).map_err(|e| e.to_string())
+			},
+			OutputFormat::Yaml => serde_yaml::to_string(value)
+				.map(String::into_bytes)
+				.map_err(|e| e.to_string()),
+			OutputFormat::Toml => toml::to_string(value)
+				.map(String::into_bytes)
+				.map_err(|e| format!("cannot be represented as TOML: {e}")),
+			OutputFormat::MsgPack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+			OutputFormat::Cbor => {
+				let mut buf = vec![];
+				ciborium::into_writer(value, &mut buf).map_err(|e| e.to_string())?;
+				Ok(buf)
+			},
+			OutputFormat::Flat => Ok(to_flat_lines(value).into_bytes()),
+			OutputFormat::Png => Err("only images can be converted to PNG".to_owned()),
+		}
+	}
+
+	/// Name of a file once converted, BLKs keep theirs as text or JSON and have the format's extension appended otherwise
+	pub fn output_path(self, path: &str) -> Cow<'_, str> {
+		if self == OutputFormat::Png && is_image(path) {
+			return Cow::Owned(
+				Path::new(path)
+					.with_extension("png")
+					.to_string_lossy()
+					.into_owned(),
+			);
+		}
+		let extension = match self {
+			OutputFormat::Yaml => "yaml",
+			OutputFormat::Toml => "toml",
+			OutputFormat::MsgPack => "msgpack",
+			OutputFormat::Cbor => "cbor",
+			_ => return Cow::Borrowed(path),
+		};
+		if is_blk(path) {
+			Cow::Owned(format!("{path}.{extension}"))
+		} else {
+			Cow::Borrowed(path)
+		}
+	}
+}
+
+pub fn is_blk(path: &str) -> bool {
+	path.ends_with("blk")
+}
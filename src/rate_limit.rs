@@ -0,0 +1,330 @@
+use std::{
+	str::FromStr,
+	sync::{
+		atomic::{AtomicI64, AtomicU64, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
+use axum::{
+	extract::{Request, State},
+	middleware::Next,
+	response::Response,
+};
+use http::{header::RETRY_AFTER, HeaderMap, HeaderValue, StatusCode};
+use octocrab::{FromResponse, Octocrab};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::{
+	sync::{Semaphore, SemaphorePermit},
+	time::sleep,
+};
+use tracing::warn;
+
+use crate::{app_state::AppState, error::ApiError, eyre_error_translation::EyreToApiError};
+
+const MAX_ATTEMPTS: u32 = 4;
+/// Doubled after every failed attempt, plus up to the same amount of jitter
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Waiting longer for the quota to reset fails the request with 503 instead
+const MAX_RESET_WAIT: Duration = Duration::from_secs(30);
+/// GitHub API requests in flight at the same time
+const MAX_CONCURRENT: usize = 8;
+/// Of MAX_CONCURRENT, background work may only take this many, the rest stays free for user requests
+const MAX_BACKGROUND: usize = 2;
+
+/// Who is waiting on a GitHub request
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Priority {
+	/// Served to a client as part of its request
+	User,
+	/// Cache refreshes and warmup
+	Background,
+}
+
+/// Last known core quota of the GitHub API, and the requests currently using it.
+/// The quota is seeded from /rate_limit at startup, and then taken from the headers of every response
+pub struct RateLimit {
+	// Handed out in FIFO order, so that waiting requests are served fairly
+	permits:         Semaphore,
+	background:      Semaphore,
+	limit:           AtomicU64,
+	remaining:       AtomicU64,
+	reset:           AtomicI64,
+	// Unix timestamp of the last quota update, 0 before the first one
+	checked_at:      AtomicI64,
+	// Unix timestamp until which no requests are sent to GitHub
+	throttled_until: AtomicI64,
+}
+
+/// Quota as of the last GitHub response at `checked_at`
+#[derive(Debug, Clone, Serialize)]
+pub struct Quota {
+	/// Requests per hour, 60 without GH_TOKEN
+	limit:      u64,
+	remaining:  u64,
+	/// Unix timestamp at which the quota resets
+	reset:      i64,
+	/// Unix timestamp of the last update
+	checked_at: i64,
+}
+
+// Subset of https://docs.github.com/en/rest/rate-limit/rate-limit
+#[derive(Deserialize)]
+struct RateLimitResponse {
+	resources: Resources,
+}
+
+#[derive(Deserialize)]
+struct Resources {
+	core: CoreQuota,
+}
+
+#[derive(Deserialize)]
+struct CoreQuota {
+	limit:     u64,
+	remaining: u64,
+	reset:     i64,
+}
+
+impl Default for RateLimit {
+	fn default() -> Self {
+		Self {
+			permits:         Semaphore::new(MAX_CONCURRENT),
+			background:      Semaphore::new(MAX_BACKGROUND),
+			limit:           Default::default(),
+			remaining:       Default::default(),
+			reset:           Default::default(),
+			checked_at:      Default::default(),
+			throttled_until: Default::default(),
+		}
+	}
+}
+
+impl RateLimit {
+	/// Polls the current quota, which does not count against it.
+	/// Only needed before the first request, responses keep the quota up to date afterwards
+	pub async fn refresh(&self, octo: &Octocrab) {
+		match octo
+			.get::<RateLimitResponse, _, ()>("/rate_limit", None)
+			.await
+		{
+			Ok(res) => {
+				let core = res.resources.core;
+				self.store(core.limit, core.remaining, core.reset);
+			},
+			Err(e) => warn!("Failed to query the GitHub rate limit. Reason: {e}"),
+		}
+	}
+
+	fn store(&self, limit: u64, remaining: u64, reset: i64) {
+		self.limit.store(limit, Ordering::Relaxed);
+		// Responses may arrive out of order, within one window the quota only goes down
+		if self.reset.swap(reset, Ordering::Relaxed) == reset {
+			self.remaining.fetch_min(remaining, Ordering::Relaxed);
+		} else {
+			self.remaining.store(remaining, Ordering::Relaxed);
+		}
+		self.checked_at.store(now(), Ordering::Relaxed);
+	}
+
+	/// Takes the quota from the `x-ratelimit-*` headers GitHub sends with every response
+	fn update(&self, headers: &HeaderMap) {
+		// Other resources, like search, have quotas of their own
+		if header::<String>(headers, "x-ratelimit-resource").is_some_and(|r| r != "core") {
+			return;
+		}
+		if let (Some(limit), Some(remaining), Some(reset)) = (
+			header(headers, "x-ratelimit-limit"),
+			header(headers, "x-ratelimit-remaining"),
+			header(headers, "x-ratelimit-reset"),
+		) {
+			self.store(limit, remaining, reset);
+		}
+	}
+
+	pub fn quota(&self) -> Option<Quota> {
+		let checked_at = self.checked_at.load(Ordering::Relaxed);
+		(checked_at != 0).then(|| Quota {
+			limit: self.limit.load(Ordering::Relaxed),
+			remaining: self.remaining.load(Ordering::Relaxed),
+			reset: self.reset.load(Ordering::Relaxed),
+			checked_at,
+		})
+	}
+
+	/// Seconds until GitHub is asked again, if it is throttling us
+	pub fn retry_after(&self) -> Option<i64> {
+		let wait = self.throttled_until.load(Ordering::Relaxed) - now();
+		(wait > 0).then_some(wait)
+	}
+
+	/// Sends a GET request to a GitHub API route, retrying transient errors and waiting out short rate limits
+	pub async fn call<T: FromResponse>(
+		&self,
+		octo: &Octocrab,
+		priority: Priority,
+		route: &str,
+	) -> ApiError<T> {
+		if let Some(wait) = self.retry_after() {
+			return Err(throttled(wait));
+		}
+
+		let mut backoff = INITIAL_BACKOFF;
+		let mut attempt = 1;
+		loop {
+			let res = {
+				let _permit = self.acquire(priority).await;
+				self.send(octo, route).await
+			};
+			let e = match res {
+				Ok(res) => return Ok(res),
+				Err(e) => e,
+			};
+
+			let wait = if is_rate_limited(&e) {
+				// The quota was updated from the headers of the rate limited response
+				let until_reset = self.reset.load(Ordering::Relaxed) - now();
+				// With quota left, the secondary rate limit was hit, which has no known reset
+				let wait = if self.remaining.load(Ordering::Relaxed) == 0 && until_reset > 0 {
+					Duration::from_secs(until_reset as u64)
+				} else {
+					backoff
+				};
+				if wait > MAX_RESET_WAIT || attempt == MAX_ATTEMPTS {
+					let wait = wait.as_secs().max(1) as i64;
+					self.throttled_until.store(now() + wait, Ordering::Relaxed);
+					return Err(throttled(wait));
+				}
+				wait
+			} else if is_transient(&e) && attempt < MAX_ATTEMPTS {
+				backoff
+			} else {
+				return Err(e).convert_err();
+			};
+
+			let jitter = rand::thread_rng().gen_range(Duration::ZERO..=backoff);
+			warn!("GitHub request failed on attempt {attempt}, retrying in {wait:?}. Reason: {e}");
+			sleep(wait + jitter).await;
+			backoff *= 2;
+			attempt += 1;
+		}
+	}
+
+	async fn send<T: FromResponse>(&self, octo: &Octocrab, route: &str) -> octocrab::Result<T> {
+		let res = octo._get(route).await?;
+		self.update(res.headers());
+		let res = octocrab::map_github_error(res).await?;
+		T::from_response(res).await
+	}
+
+	/// Holds a share of the concurrent requests until the permits are dropped
+	async fn acquire(
+		&self,
+		priority: Priority,
+	) -> (Option<SemaphorePermit<'_>>, SemaphorePermit<'_>) {
+		// Background work queues for its own permit first, so it never holds a shared one while waiting
+		let background = match priority {
+			Priority::User => None,
+			Priority::Background => Some(
+				self.background
+					.acquire()
+					.await
+					.expect("semaphore to never be closed"),
+			),
+		};
+		let permit = self
+			.permits
+			.acquire()
+			.await
+			.expect("semaphore to never be closed");
+		(background, permit)
+	}
+}
+
+/// Adds Retry-After to 503 responses while GitHub is throttling us
+pub async fn retry_after_header(
+	State(state): State<Arc<AppState>>,
+	req: Request,
+	next: Next,
+) -> Response {
+	let mut res = next.run(req).await;
+	if res.status() == StatusCode::SERVICE_UNAVAILABLE {
+		if let Some(wait) = state.rate_limit.retry_after() {
+			res.headers_mut()
+				.insert(RETRY_AFTER, HeaderValue::from(wait));
+		}
+	}
+	res
+}
+
+fn is_rate_limited(e: &octocrab::Error) -> bool {
+	match e {
+		octocrab::Error::GitHub { source, .. } => {
+			source.status_code == StatusCode::TOO_MANY_REQUESTS
+				|| (source.status_code == StatusCode::FORBIDDEN
+					&& source.message.to_lowercase().contains("rate limit"))
+		},
+		_ => false,
+	}
+}
+
+fn is_transient(e: &octocrab::Error) -> bool {
+	match e {
+		octocrab::Error::GitHub { source, .. } => source.status_code.is_server_error(),
+		octocrab::Error::Hyper { .. } | octocrab::Error::Service { .. } => true,
+		_ => false,
+	}
+}
+
+fn header<T: FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+	headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+fn throttled(wait: i64) -> (StatusCode, String) {
+	(
+		StatusCode::SERVICE_UNAVAILABLE,
+		format!("GitHub rate limit exceeded, retry in {wait} seconds"),
+	)
+}
+
+fn now() -> i64 {
+	OffsetDateTime::now_utc().unix_timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn headers(resource: &str, remaining: u64, reset: i64) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		headers.insert("x-ratelimit-resource", resource.parse().unwrap());
+		headers.insert("x-ratelimit-limit", HeaderValue::from(5000));
+		headers.insert("x-ratelimit-remaining", HeaderValue::from(remaining));
+		headers.insert("x-ratelimit-reset", HeaderValue::from(reset));
+		headers
+	}
+
+	#[test]
+	fn quota_follows_response_headers() {
+		let rate_limit = RateLimit::default();
+		assert!(rate_limit.quota().is_none());
+
+		rate_limit.update(&headers("core", 4000, 100));
+		// A response sent earlier in the same window arrives late
+		rate_limit.update(&headers("core", 4001, 100));
+		rate_limit.update(&headers("search", 10, 200));
+		let quota = rate_limit.quota().unwrap();
+		assert_eq!(
+			(quota.limit, quota.remaining, quota.reset),
+			(5000, 4000, 100)
+		);
+
+		// A new window starts over
+		rate_limit.update(&headers("core", 4999, 200));
+		let quota = rate_limit.quota().unwrap();
+		assert_eq!((quota.remaining, quota.reset), (4999, 200));
+	}
+}
@@ -0,0 +1,270 @@
+use std::{path::Path, sync::Arc};
+
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+use wt_blk::vromf::{BlkOutputFormat, File, VromfUnpacker};
+use wt_version::Version;
+
+use crate::{
+	app_state::AppState,
+	endpoints::{
+		files::{FileEntry, UnpackedVromfs},
+		get_vromfs::download_single_vromf,
+	},
+	error::ApiError,
+	eyre_error_translation::EyreToApiError,
+	lang_table::LangTable,
+	rate_limit::Priority,
+	vromf_enum::VromfType,
+};
+
+/// Catalogs kept in memory, enough for the widest unit history
+pub const MAX_CACHED_CATALOGS: u64 = 64;
+/// Economy data of every unit, keyed by unit ID
+const WPCOST_PATH: &str = "config/wpcost.blk";
+/// Folders in aces.vromfs.bin holding the full definition of units
+const UNIT_BLK_FOLDERS: &[&str] = &[
+	"gamedata/flightmodels",
+	"gamedata/units/tankmodels",
+	"gamedata/units/ships",
+];
+
+/// Every unit of a version, as listed in wpcost.blk
+#[derive(Default)]
+pub struct UnitCatalog {
+	pub units: Vec<Unit>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Unit {
+	#[schema(example = "f_16a")]
+	pub id:             String,
+	/// Localized name, filled in per request
+	#[schema(example = "F-16A")]
+	pub name:           Option<String>,
+	/// Country without the `country_` prefix
+	#[schema(example = "usa")]
+	pub country:        String,
+	#[schema(example = "exp_fighter")]
+	pub unit_class:     String,
+	pub unit_type:      UnitType,
+	#[schema(example = 7)]
+	pub rank:           u64,
+	pub battle_ratings: BattleRatings,
+	/// Research points required to unlock the unit
+	pub research_cost:  u64,
+	/// Silver lions required to buy the unit
+	pub purchase_cost:  u64,
+	pub repair_costs:   RepairCosts,
+	/// Names of the weapon presets the unit can be armed with
+	pub weapon_presets: Vec<String>,
+	/// BLK defining the unit within aces.vromfs.bin, filled in per request
+	#[schema(example = "gamedata/flightmodels/f_16a.blk")]
+	pub blk_path:       Option<String>,
+}
+
+#[derive(
+	Debug, Clone, Copy, Eq, PartialEq, Serialize, ToSchema, strum::Display, strum::EnumString,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum UnitType {
+	Aircraft,
+	Helicopter,
+	Ground,
+	Fleet,
+	Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct BattleRatings {
+	pub arcade:    f64,
+	pub realistic: f64,
+	pub simulator: f64,
+}
+
+/// Silver lions per mode
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, ToSchema)]
+pub struct RepairCosts {
+	pub arcade:    u64,
+	pub realistic: u64,
+	pub simulator: u64,
+}
+
+impl UnitCatalog {
+	/// Returns the unit catalog of a version, parsing it on first use
+	pub async fn get(state: Arc<AppState>, version: Version) -> ApiError<Arc<Self>> {
+		if let Some(catalog) = state.unit_catalogs.get(&version).await {
+			return Ok(catalog);
+		}
+
+		// Versions which are not cached already only need wpcost.blk, so only their char.vromfs.bin is downloaded
+		// and unpacked once, instead of downloading, caching and indexing all of their vromfs
+		let uncached = if state.vromf_cache.is_cached(version) {
+			UnpackedVromfs::cache_unpacker(state.clone(), version, VromfType::Char).await?;
+			None
+		} else {
+			Some(
+				download_single_vromf(state.clone(), version, VromfType::Char, Priority::User)
+					.await?,
+			)
+		};
+
+		let catalog = state
+			.clone()
+			.spawn_worker(move |s| {
+				let res = || {
+					let wpcost = Path::new(WPCOST_PATH);
+					let file = match uncached {
+						Some(buf) => VromfUnpacker::from_file(
+							&File::from_raw(VromfType::Char.into(), buf),
+							false,
+						)
+						.convert_err()?
+						.unpack_one(wpcost, Some(BlkOutputFormat::Json), true),
+						None => state
+							.unpacked_vromfs
+							.unpacker(version, VromfType::Char)?
+							.unpack_one(wpcost, Some(BlkOutputFormat::Json), true),
+					};
+					let (_, buf) = file.convert_err()?.split();
+					let wpcost = serde_json::from_slice::<Value>(&buf).convert_err()?;
+					Ok(Arc::new(Self::from_wpcost(&wpcost)))
+				};
+				let _ = s.send(res());
+			})
+			.await??;
+
+		state.unit_catalogs.insert(version, catalog.clone()).await;
+		Ok(catalog)
+	}
+
+	fn from_wpcost(wpcost: &Value) -> Self {
+		let Some(entries) = wpcost.as_object() else {
+			return Self::default();
+		};
+
+		let mut units = entries
+			.iter()
+			// Besides units, wpcost.blk holds a few global economy parameters
+			.filter_map(|(id, unit)| {
+				let unit_class = unit.get("unitClass")?.as_str()?.to_owned();
+				Some(Unit {
+					id: id.clone(),
+					name: None,
+					country: unit
+						.get("country")
+						.and_then(Value::as_str)
+						.map(|c| c.trim_start_matches("country_").to_owned())
+						.unwrap_or_default(),
+					unit_type: UnitType::from_class(&unit_class),
+					unit_class,
+					rank: integer(unit, "rank"),
+					battle_ratings: BattleRatings {
+						arcade:    battle_rating(unit, "economicRankArcade"),
+						realistic: battle_rating(unit, "economicRankHistorical"),
+						simulator: battle_rating(unit, "economicRankSimulation"),
+					},
+					research_cost: integer(unit, "reqExp"),
+					purchase_cost: integer(unit, "value"),
+					repair_costs: RepairCosts {
+						arcade:    integer(unit, "repairCostArcade"),
+						realistic: integer(unit, "repairCostHistorical"),
+						simulator: integer(unit, "repairCostSimulation"),
+					},
+					weapon_presets: unit
+						.get("weapons")
+						.and_then(Value::as_object)
+						.map(|presets| presets.keys().cloned().collect())
+						.unwrap_or_default(),
+					blk_path: None,
+				})
+			})
+			.collect::<Vec<_>>();
+		units.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+		Self { units }
+	}
+
+	pub fn unit(&self, id: &str) -> Option<&Unit> {
+		self.units
+			.binary_search_by(|u| u.id.as_str().cmp(id))
+			.ok()
+			.map(|i| &self.units[i])
+	}
+}
+
+impl Unit {
+	/// Looks up the BLK defining the unit within the file index of aces.vromfs.bin
+	pub fn find_blk<'a>(&self, aces_index: &'a [FileEntry]) -> Option<&'a str> {
+		UNIT_BLK_FOLDERS.iter().find_map(|folder| {
+			let path = format!("{folder}/{}.blk", self.id);
+			aces_index
+				.binary_search_by(|e| e.path.as_str().cmp(&path))
+				.ok()
+				.map(|i| aces_index[i].path.as_str())
+		})
+	}
+
+	/// Copy of the unit named in the given language, preferring the name shown in the shop
+	pub fn localized(&self, lang: &LangTable, language: usize) -> Self {
+		let name = lang
+			.localize(&format!("{}_shop", self.id), language)
+			.or_else(|| lang.localize(&format!("{}_0", self.id), language));
+		Self {
+			name: name.map(ToOwned::to_owned),
+			..self.clone()
+		}
+	}
+}
+
+impl UnitType {
+	fn from_class(class: &str) -> Self {
+		match class {
+			"exp_helicopter" => Self::Helicopter,
+			"exp_fighter" | "exp_bomber" | "exp_assault" => Self::Aircraft,
+			"exp_tank" | "exp_heavy_tank" | "exp_tank_destroyer" | "exp_SPAA" => Self::Ground,
+			"exp_destroyer"
+			| "exp_cruiser"
+			| "exp_battleship"
+			| "exp_battlecruiser"
+			| "exp_submarine_chaser"
+			| "exp_naval_ferry_barge" => Self::Fleet,
+			// Coastal fleet classes such as exp_torpedo_boat or exp_gun_boat
+			_ if class.contains("boat") => Self::Fleet,
+			_ => Self::Other,
+		}
+	}
+}
+
+impl BattleRatings {
+	pub fn get(&self, mode: GameMode) -> f64 {
+		match mode {
+			GameMode::Arcade => self.arcade,
+			GameMode::Realistic => self.realistic,
+			GameMode::Simulator => self.simulator,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, Default, strum::EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum GameMode {
+	#[strum(serialize = "arcade", serialize = "ab")]
+	Arcade,
+	#[default]
+	#[strum(serialize = "realistic", serialize = "rb", serialize = "historical")]
+	Realistic,
+	#[strum(serialize = "simulator", serialize = "sb", serialize = "simulation")]
+	Simulator,
+}
+
+fn integer(unit: &Value, key: &str) -> u64 {
+	unit.get(key).and_then(Value::as_u64).unwrap_or_default()
+}
+
+/// Economic ranks count in thirds of a battle rating, starting at 1.0
+fn battle_rating(unit: &Value, key: &str) -> f64 {
+	let rank = unit.get(key).and_then(Value::as_f64).unwrap_or_default();
+	((rank / 3.0 + 1.0) * 10.0).round() / 10.0
+}
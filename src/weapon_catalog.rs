@@ -0,0 +1,183 @@
+use std::{path::Path, sync::Arc};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::warn;
+use utoipa::ToSchema;
+use wt_blk::vromf::BlkOutputFormat;
+use wt_version::Version;
+
+use crate::{
+	app_state::AppState,
+	endpoints::files::UnpackedVromfs,
+	error::ApiError,
+	lang_table::LangTable,
+	vromf_enum::VromfType,
+};
+
+const WEAPONS_FOLDER: &str = "gamedata/weapons/";
+
+/// Every weapon BLK of a version, reduced to its key parameters
+#[derive(Default)]
+pub struct WeaponCatalog {
+	pub weapons: Vec<Weapon>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Weapon {
+	/// File name of the BLK without extension
+	#[schema(example = "us_aim9b_sidewinder")]
+	pub id:             String,
+	/// Localized name, filled in per request
+	#[schema(example = "AIM-9B")]
+	pub name:           Option<String>,
+	#[schema(example = "gamedata/weapons/rocketguns/us_aim9b_sidewinder.blk")]
+	pub path:           String,
+	/// Folder within gamedata/weapons
+	#[schema(example = "rocketguns")]
+	pub category:       String,
+	pub kind:           WeaponKind,
+	/// Meters, as stored in the BLK
+	pub caliber:        Option<f64>,
+	/// Kilograms
+	pub mass:           Option<f64>,
+	/// Kilograms
+	pub explosive_mass: Option<f64>,
+	#[schema(example = "tnt")]
+	pub explosive_type: Option<String>,
+	/// Meters
+	pub max_distance:   Option<f64>,
+	/// Meters per second, the end speed for rockets
+	pub speed:          Option<f64>,
+	/// Bullet type for guns, guidance type for guided munitions
+	#[schema(example = "ir")]
+	pub ammo_type:      Option<String>,
+}
+
+#[derive(
+	Debug, Clone, Copy, Eq, PartialEq, Serialize, ToSchema, strum::Display, strum::EnumString,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum WeaponKind {
+	Rocket,
+	Bomb,
+	Torpedo,
+	Bullet,
+	Other,
+}
+
+impl WeaponCatalog {
+	/// Returns the weapon catalog of a version, parsing it on first use
+	pub async fn get(state: Arc<AppState>, version: Version) -> ApiError<Arc<Self>> {
+		if let Some(catalog) = state.weapon_catalogs.get(&version) {
+			return Ok(catalog.clone());
+		}
+
+		let paths = UnpackedVromfs::file_index(state.clone(), version, VromfType::Aces)
+			.await?
+			.iter()
+			.filter(|e| e.path.starts_with(WEAPONS_FOLDER) && e.path.ends_with(".blk"))
+			.map(|e| e.path.clone())
+			.collect::<Vec<_>>();
+
+		let catalog = state
+			.clone()
+			.spawn_worker(move |s| {
+				let res = || {
+					let unpacker = state.unpacked_vromfs.unpacker(version, VromfType::Aces)?;
+					let weapons = paths
+						.par_iter()
+						.filter_map(|path| {
+							let value = unpacker
+								.unpack_one(Path::new(path), Some(BlkOutputFormat::Json), true)
+								.map_err(|e| e.to_string())
+								.and_then(|file| {
+									serde_json::from_slice::<Value>(&file.split().1)
+										.map_err(|e| e.to_string())
+								});
+							match value {
+								Ok(value) => Some(Weapon::from_blk(path, &value)),
+								Err(e) => {
+									warn!("Skipping weapon {path}. Reason: {e}");
+									None
+								},
+							}
+						})
+						.collect::<Vec<_>>();
+					// The file index is sorted by path already, which par_iter preserves
+					Ok(Arc::new(Self { weapons }))
+				};
+				let _ = s.send(res());
+			})
+			.await??;
+
+		state.weapon_catalogs.insert(version, catalog.clone());
+		Ok(catalog)
+	}
+}
+
+impl Weapon {
+	fn from_blk(path: &str, value: &Value) -> Self {
+		let relative = path.trim_start_matches(WEAPONS_FOLDER);
+		let category = relative
+			.rsplit_once('/')
+			.map(|(folder, _)| folder.to_owned())
+			.unwrap_or_default();
+		let id = Path::new(path)
+			.file_stem()
+			.map(|s| s.to_string_lossy().into_owned())
+			.unwrap_or_default();
+
+		// Projectiles are defined in a block named after their kind, guns may repeat it per belt
+		let (kind, ammo) = [
+			("rocket", WeaponKind::Rocket),
+			("bomb", WeaponKind::Bomb),
+			("torpedo", WeaponKind::Torpedo),
+			("bullet", WeaponKind::Bullet),
+		]
+		.into_iter()
+		.find_map(|(key, kind)| Some((kind, first(value.get(key)?))))
+		.unwrap_or((WeaponKind::Other, value));
+
+		let number = |keys: &[&str]| keys.iter().find_map(|k| first(ammo.get(*k)?).as_f64());
+		let string = |keys: &[&str]| {
+			keys.iter()
+				.find_map(|k| first(ammo.get(*k)?).as_str().map(ToOwned::to_owned))
+		};
+		Self {
+			id,
+			name: None,
+			path: path.to_owned(),
+			category,
+			kind,
+			caliber: number(&["caliber"]),
+			mass: number(&["mass"]),
+			explosive_mass: number(&["explosiveMass"]),
+			explosive_type: string(&["explosiveType"]),
+			max_distance: number(&["maxDistance", "distance"]),
+			speed: number(&["endSpeed", "speed", "maxSpeed"]),
+			ammo_type: string(&["bulletType", "guidanceType"]),
+		}
+	}
+
+	/// Copy of the weapon named in the given language
+	pub fn localized(&self, lang: &LangTable, language: usize) -> Self {
+		let name = lang
+			.localize(&format!("weapons/{}/short", self.id), language)
+			.or_else(|| lang.localize(&format!("weapons/{}", self.id), language));
+		Self {
+			name: name.map(ToOwned::to_owned),
+			..self.clone()
+		}
+	}
+}
+
+/// Repeated BLK blocks decode to arrays, of which the first entry is representative
+fn first(value: &Value) -> &Value {
+	match value {
+		Value::Array(values) => values.first().unwrap_or(value),
+		other => other,
+	}
+}
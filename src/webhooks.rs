@@ -0,0 +1,325 @@
+use std::{
+	collections::VecDeque,
+	env,
+	fs,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc,
+		Mutex,
+	},
+	time::Duration,
+};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use time::OffsetDateTime;
+use tokio::{sync::broadcast::error::RecvError, time::sleep};
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::{app_state::AppState, events::VersionEvent};
+
+const MAX_ATTEMPTS: u32 = 6;
+/// Doubled after every failed attempt
+const INITIAL_BACKOFF: Duration = if cfg!(test) {
+	Duration::from_millis(10)
+} else {
+	Duration::from_secs(2)
+};
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Deliveries kept in the log, oldest are dropped first
+const DELIVERY_LOG_SIZE: usize = 200;
+
+/// Subscription loaded from the JSON array in the file WEBHOOKS_FILE points to
+#[derive(Debug, Deserialize)]
+pub struct Webhook {
+	/// Shown in the delivery log instead of the URL, which may contain credentials
+	name:   String,
+	url:    String,
+	/// Key of the HMAC-SHA256 signature sent in X-Signature-256
+	secret: String,
+	/// Only fire when one of these vromfs changed, empty fires for every new version
+	#[serde(default)]
+	vromfs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Delivery {
+	id:          u64,
+	webhook:     String,
+	version:     String,
+	attempts:    u32,
+	delivered:   bool,
+	/// HTTP status of the last attempt, if the receiver responded at all
+	status:      Option<u16>,
+	error:       Option<String>,
+	/// Unix timestamp of the last attempt
+	finished_at: i64,
+}
+
+#[derive(Default)]
+pub struct Webhooks {
+	hooks:            Vec<Arc<Webhook>>,
+	deliveries:       Mutex<VecDeque<Delivery>>,
+	next_delivery_id: AtomicU64,
+	client:           reqwest::Client,
+}
+
+impl Webhooks {
+	/// Loads subscriptions from WEBHOOKS_FILE, a missing variable means no webhooks
+	pub fn from_env() -> Self {
+		let Ok(path) = env::var("WEBHOOKS_FILE") else {
+			return Self::default();
+		};
+		let hooks = fs::read(&path).map_err(|e| e.to_string()).and_then(|buf| {
+			serde_json::from_slice::<Vec<Webhook>>(&buf).map_err(|e| e.to_string())
+		});
+		match hooks {
+			Ok(hooks) => {
+				info!("Loaded {} webhooks from {path}", hooks.len());
+				Self {
+					hooks: hooks.into_iter().map(Arc::new).collect(),
+					..Default::default()
+				}
+			},
+			Err(e) => {
+				error!("Failed to load webhooks from {path}, none will fire. Reason: {e}");
+				Self::default()
+			},
+		}
+	}
+
+	/// Most recent deliveries first
+	pub fn deliveries(&self) -> Vec<Delivery> {
+		let log = self.deliveries.lock().expect("delivery log lock poisoned");
+		log.iter().rev().cloned().collect()
+	}
+
+	fn log(&self, delivery: Delivery) {
+		let mut log = self.deliveries.lock().expect("delivery log lock poisoned");
+		if log.len() >= DELIVERY_LOG_SIZE {
+			log.pop_front();
+		}
+		log.push_back(delivery);
+	}
+}
+
+impl Webhook {
+	fn wants(&self, event: &VersionEvent) -> bool {
+		self.vromfs.is_empty()
+			|| event
+				.changed_vromfs
+				.iter()
+				.any(|changed| self.vromfs.iter().any(|v| v == changed))
+	}
+}
+
+/// Fires the configured webhooks for every version event
+pub fn spawn_dispatcher(state: Arc<AppState>) {
+	if state.webhooks.hooks.is_empty() {
+		return;
+	}
+	let mut rx = state.version_events.subscribe();
+	tokio::spawn(async move {
+		loop {
+			let event = match rx.recv().await {
+				Ok(event) => event,
+				Err(RecvError::Lagged(skipped)) => {
+					warn!("Webhook dispatcher skipped {skipped} events");
+					continue;
+				},
+				Err(RecvError::Closed) => return,
+			};
+			for hook in state.webhooks.hooks.iter().filter(|h| h.wants(&event)) {
+				tokio::spawn(deliver(state.clone(), hook.clone(), event.clone()));
+			}
+		}
+	});
+}
+
+async fn deliver(state: Arc<AppState>, hook: Arc<Webhook>, event: Arc<VersionEvent>) {
+	let webhooks = &state.webhooks;
+	let id = webhooks.next_delivery_id.fetch_add(1, Ordering::Relaxed);
+	let body = serde_json::to_vec(&*event).expect("event to serialize");
+	let mut mac =
+		Hmac::<Sha256>::new_from_slice(hook.secret.as_bytes()).expect("HMAC accepts any key size");
+	mac.update(&body);
+	let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+
+	let mut delivery = Delivery {
+		id,
+		webhook: hook.name.clone(),
+		version: event.version.clone(),
+		attempts: 0,
+		delivered: false,
+		status: None,
+		error: None,
+		finished_at: 0,
+	};
+	let mut backoff = INITIAL_BACKOFF;
+	while delivery.attempts < MAX_ATTEMPTS {
+		delivery.attempts += 1;
+		let res = webhooks
+			.client
+			.post(&hook.url)
+			.timeout(REQUEST_TIMEOUT)
+			.header("Content-Type", "application/json")
+			.header("X-Event", "version")
+			.header("X-Delivery", id)
+			.header("X-Signature-256", &signature)
+			.body(body.clone())
+			.send()
+			.await;
+
+		// Client errors besides rate limiting will not resolve by retrying
+		let retry = match res {
+			Ok(res) => {
+				let status = res.status();
+				delivery.status = Some(status.as_u16());
+				delivery.delivered = status.is_success();
+				delivery.error = None;
+				!status.is_success()
+					&& (status.is_server_error()
+						|| status == reqwest::StatusCode::TOO_MANY_REQUESTS)
+			},
+			Err(e) => {
+				delivery.status = None;
+				delivery.error = Some(e.to_string());
+				true
+			},
+		};
+		if !retry {
+			break;
+		}
+		if delivery.attempts < MAX_ATTEMPTS {
+			sleep(backoff).await;
+			backoff *= 2;
+		}
+	}
+
+	delivery.finished_at = OffsetDateTime::now_utc().unix_timestamp();
+	if delivery.delivered {
+		info!("Delivered webhook {} for {}", hook.name, event.version);
+	} else {
+		warn!(
+			"Giving up on webhook {} for {} after {} attempts",
+			hook.name, event.version, delivery.attempts
+		);
+	}
+	webhooks.log(delivery);
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use axum::{
+		body::Bytes,
+		extract::{Path, State},
+		http::{HeaderMap, StatusCode},
+		routing::post,
+		Json,
+		Router,
+	};
+	use tokio::{net::TcpListener, time::timeout};
+	use wt_version::Version;
+
+	use super::*;
+	use crate::{endpoints::webhooks::webhook_deliveries, events, vromf_enum::VromfType};
+
+	const SECRET: &str = "hunter2";
+
+	/// Requests per webhook, and whether their signature matched
+	type Received = Arc<Mutex<HashMap<String, Vec<bool>>>>;
+
+	async fn receive(
+		State(received): State<Received>,
+		Path(name): Path<String>,
+		headers: HeaderMap,
+		body: Bytes,
+	) -> StatusCode {
+		let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+		mac.update(&body);
+		let expected = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+		let signed =
+			headers.get("X-Signature-256").and_then(|v| v.to_str().ok()) == Some(expected.as_str());
+
+		let mut received = received.lock().unwrap();
+		let requests = received.entry(name.clone()).or_default();
+		requests.push(signed);
+		match (name.as_str(), requests.len()) {
+			("flaky", 1) => StatusCode::INTERNAL_SERVER_ERROR,
+			("flaky", 2) => StatusCode::TOO_MANY_REQUESTS,
+			("flaky", _) => StatusCode::OK,
+			_ => StatusCode::BAD_REQUEST,
+		}
+	}
+
+	#[tokio::test]
+	async fn deliveries_are_signed_and_retried() {
+		let received = Received::default();
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let base = format!("http://{}", listener.local_addr().unwrap());
+		let app = Router::new()
+			.route("/:name", post(receive))
+			.with_state(received.clone());
+		tokio::spawn(async move { axum::serve(listener, app).await });
+
+		let mut state = AppState::default();
+		let hook = |name: &str, vromfs: &[&str]| {
+			Arc::new(Webhook {
+				name:   name.to_owned(),
+				url:    format!("{base}/{name}"),
+				secret: SECRET.to_owned(),
+				vromfs: vromfs.iter().map(|v| v.to_string()).collect(),
+			})
+		};
+		state.webhooks = Webhooks {
+			hooks: vec![
+				hook("flaky", &[]),
+				hook("rejected", &["aces.vromfs.bin"]),
+				hook("unrelated", &["lang.vromfs.bin"]),
+			],
+			..Default::default()
+		};
+		let state = Arc::new(state);
+		spawn_dispatcher(state.clone());
+		events::publish(
+			&state,
+			VersionEvent::new(
+				Version::new(2, 41, 0, 25),
+				"f".repeat(40),
+				vec![VromfType::Aces],
+			),
+		);
+
+		let deliveries = timeout(Duration::from_secs(10), async {
+			loop {
+				let Json(deliveries) = webhook_deliveries(State(state.clone())).await;
+				if deliveries.len() == 2 {
+					return deliveries;
+				}
+				sleep(Duration::from_millis(10)).await;
+			}
+		})
+		.await
+		.expect("both deliveries to finish");
+
+		let delivery = |name: &str| deliveries.iter().find(|d| d.webhook == name).unwrap();
+		let flaky = delivery("flaky");
+		assert!(flaky.delivered);
+		assert_eq!(flaky.attempts, 3);
+		assert_eq!(flaky.status, Some(200));
+		assert_eq!(flaky.version, "2.41.0.25");
+		let rejected = delivery("rejected");
+		assert!(!rejected.delivered);
+		assert_eq!(rejected.attempts, 1);
+		assert_eq!(rejected.status, Some(400));
+
+		let received = received.lock().unwrap();
+		assert_eq!(received["flaky"], vec![true; 3]);
+		assert_eq!(received["rejected"], vec![true]);
+		assert!(!received.contains_key("unrelated"));
+	}
+}